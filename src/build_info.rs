@@ -1,5 +1,8 @@
+use once_cell::sync::OnceCell;
 use serde::Serialize;
 
+static BUILD_INFO: OnceCell<BuildInfo> = OnceCell::new();
+
 /// Returns a struct with the current project build information (Rust, Cargo and Git).
 ///
 /// To use this macro, a build.rs file must be configured using [Vergen default configuration](https://docs.rs/vergen/latest/vergen/#buildrs),
@@ -8,6 +11,7 @@ use serde::Serialize;
 macro_rules! generate_build_info {
     () => {
         balthazar::build_info::BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
             datetime: env!("VERGEN_BUILD_TIMESTAMP"),
             rust: balthazar::build_info::RustInfo {
                 version: env!("VERGEN_RUSTC_SEMVER"),
@@ -27,8 +31,23 @@ macro_rules! generate_build_info {
     };
 }
 
+/// Registers the project build information, used to describe the service in exported telemetry.
+///
+/// Must be called before `Config::init`, usually as `build_info::register(generate_build_info!())`.
+/// Only the first registration is kept.
+pub fn register(build_info: BuildInfo) {
+    let _ = BUILD_INFO.set(build_info);
+}
+
+/// Returns the registered project build information, if any.
+pub fn current() -> Option<&'static BuildInfo> {
+    BUILD_INFO.get()
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct BuildInfo {
+    /// Version of the service crate, used as `service.version`.
+    pub version: &'static str,
     pub datetime: &'static str,
     pub rust: RustInfo,
     pub git: GitInfo,
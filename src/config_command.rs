@@ -0,0 +1,252 @@
+use std::{collections::BTreeMap, fmt::Debug, future::Future, time::Duration};
+
+use clap::{ArgMatches, Subcommand};
+use eyre::WrapErr;
+use serde::Serialize;
+
+use crate::config_source::{arg_keys, masked_value, ConfigValueSource, LoadedConfig};
+use crate::feature::SkippedFeature;
+use crate::lang::sensitive::scrub;
+use crate::{throw, Args, Config, EnvironmentConfig, FeatureRegistry, Result};
+
+/// Maximum time each dependency has to answer `check-config`.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// -----------------------------------------------------------------------------
+// Commands
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub(crate) enum ConfigCommand {
+    /// Validates the configuration, checks the telemetry endpoints are reachable and initializes
+    /// every registered feature, checking its health, without starting the service.
+    CheckConfig,
+
+    /// Prints the effective configuration as JSON, with the source of each value and `Sensitive`
+    /// values masked.
+    PrintConfig,
+}
+
+impl ConfigCommand {
+    /// Runs the command, returning the exit code of the process.
+    pub(crate) async fn run<T: Debug + Args>(
+        self,
+        service_name: &str,
+        loaded: &LoadedConfig<Config<T>>,
+        features: &FeatureRegistry,
+    ) -> i32 {
+        match self {
+            ConfigCommand::CheckConfig => {
+                check_config(
+                    service_name,
+                    &loaded.config.environment,
+                    &loaded.matches,
+                    features,
+                )
+                .await
+            }
+            ConfigCommand::PrintConfig => match effective_config(loaded) {
+                Ok(config) => {
+                    println!("{}", config);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Failed to print configuration: {:#}", e);
+                    1
+                }
+            },
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Print Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Serialize)]
+struct ConfigValue {
+    value: Option<String>,
+
+    #[serde(flatten)]
+    source: ConfigValueSource,
+}
+
+/// Every configuration value by key, as pretty JSON.
+fn effective_config<T: Debug + Args>(loaded: &LoadedConfig<Config<T>>) -> Result<String> {
+    let values: BTreeMap<String, ConfigValue> = arg_keys(&loaded.arguments)
+        .into_iter()
+        .map(|(id, key)| {
+            let value = ConfigValue {
+                value: masked_value(&loaded.matches, &id),
+                source: loaded.source(&id),
+            };
+            (key, value)
+        })
+        .collect();
+    serde_json::to_string_pretty(&values).wrap_err("Failed to serialize configuration")
+}
+
+// -----------------------------------------------------------------------------
+// Check Config
+// -----------------------------------------------------------------------------
+
+/// Checks the telemetry endpoints and every registered feature, printing the result of each one.
+async fn check_config(
+    service_name: &str,
+    config: &EnvironmentConfig,
+    matches: &ArgMatches,
+    features: &FeatureRegistry,
+) -> i32 {
+    // the configuration was already parsed and validated when the command runs
+    let mut checks = vec![("configuration", Ok(()))];
+
+    if !config.tracing.disable_opentelemetry {
+        let endpoint = &config.tracing.opentelemetry_endpoint;
+        checks.push(("opentelemetry", check(check_endpoint(endpoint)).await));
+    }
+
+    #[cfg(feature = "sentry")]
+    if let Some(dsn) = &config.tracing.sentry.sentry_dsn {
+        checks.push(("sentry", check(check_sentry_dsn(dsn)).await));
+    }
+
+    match features.check(service_name, matches, CHECK_TIMEOUT).await {
+        Ok(feature_checks) => checks.extend(feature_checks),
+        Err(e) => checks.push(("features", Err(e))),
+    }
+
+    let mut exit_code = 0;
+    for (name, result) in checks {
+        match result {
+            Ok(()) => println!("{:<15} ok", name),
+            Err(e) if e.is::<SkippedFeature>() => println!("{:<15} skipped: {}", name, e),
+            Err(e) => {
+                // errors may mention values of the configuration, like connection URLs
+                println!("{:<15} failed: {}", name, scrub(&format!("{:#}", e)));
+                exit_code = 1;
+            }
+        }
+    }
+    exit_code
+}
+
+async fn check<F>(future: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    tokio::time::timeout(CHECK_TIMEOUT, future)
+        .await
+        .wrap_err("Timed out")?
+}
+
+/// Connects to the host of an HTTP endpoint.
+async fn check_endpoint(endpoint: &str) -> Result<()> {
+    let uri: http::Uri = endpoint.parse().wrap_err("Invalid endpoint")?;
+    let host = uri
+        .host()
+        .ok_or_else(|| throw!("Endpoint without host: {}", endpoint))?;
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    });
+    check_address(host, port).await
+}
+
+#[cfg(feature = "sentry")]
+async fn check_sentry_dsn(dsn: &str) -> Result<()> {
+    let dsn: sentry::types::Dsn = dsn.parse().wrap_err("Invalid Sentry DSN")?;
+    check_address(dsn.host(), dsn.port()).await
+}
+
+async fn check_address(host: &str, port: u16) -> Result<()> {
+    tokio::net::TcpStream::connect((host, port))
+        .await
+        .wrap_err_with(|| format!("Failed to connect to {}:{}", host, port))?;
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use clap::CommandFactory;
+    use serde_json::{json, Value};
+
+    use crate::config_source::{LoadedConfig, TempFile};
+    use crate::error_reporting::install_test_hooks;
+    use crate::{Args, Config, Sensitive};
+
+    use super::{check_endpoint, effective_config, ConfigCommand};
+
+    #[derive(Debug, Args)]
+    struct Project {
+        #[clap(long = "print-name", env = "PRINT_TEST_NAME")]
+        name: String,
+
+        #[clap(long = "print-token", env = "PRINT_TEST_TOKEN")]
+        token: Sensitive<String>,
+
+        #[clap(
+            long = "print-workers",
+            env = "PRINT_TEST_WORKERS",
+            default_value = "4"
+        )]
+        workers: u32,
+    }
+
+    #[test]
+    fn print_effective_config_with_sources() {
+        let temp_file = TempFile::new("print.toml", "print_test_token = \"print-secret-token\"\n");
+        let config_file = temp_file.path().to_path_buf();
+
+        let loaded = LoadedConfig::<Config<Project>>::load(
+            Config::<Project>::command(),
+            &[
+                "test".into(),
+                "--print-name=payments".into(),
+                "--config".into(),
+                config_file.clone().into(),
+                "print-config".into(),
+            ],
+        )
+        .unwrap();
+        let config: Value = serde_json::from_str(&effective_config(&loaded).unwrap()).unwrap();
+
+        assert_eq!(loaded.command, Some(ConfigCommand::PrintConfig));
+        assert_eq!(
+            config["PRINT_TEST_NAME"],
+            json!({"value": "payments", "source": "command_line"})
+        );
+        assert_eq!(
+            config["PRINT_TEST_TOKEN"],
+            json!({"value": "******", "source": "config_file", "path": config_file})
+        );
+        assert_eq!(
+            config["PRINT_TEST_WORKERS"],
+            json!({"value": "4", "source": "default"})
+        );
+        assert_eq!(
+            config["TRACING_SAMPLE_RATE"],
+            json!({"value": null, "source": "default"})
+        );
+    }
+
+    #[tokio::test]
+    async fn check_endpoint_reachability() {
+        install_test_hooks();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(
+            check_endpoint(&format!("http://127.0.0.1:{}/api/traces", port))
+                .await
+                .is_ok()
+        );
+
+        assert!(check_endpoint("http://127.0.0.1:0/api/traces")
+            .await
+            .is_err());
+        assert!(check_endpoint("not an endpoint").await.is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use std::fmt::{Debug, Write};
+
+use clap::{Arg, ArgAction, Command, CommandFactory};
+use serde::Serialize;
+
+use crate::{
+    Args, Config, ConfigSourceConfig, CoreConfig, FeatureRegistry, HttpServerConfig,
+    LogOutputConfig, ResourceConfig, TraceLinkConfig, TracingConfig,
+};
+
+// -----------------------------------------------------------------------------
+// Reference
+// -----------------------------------------------------------------------------
+
+/// Reference of every configuration value accepted by a service, generated from the command built
+/// from [`Config`], so it documents exactly the features enabled at build time.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigReference {
+    pub entries: Vec<ConfigReferenceEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigReferenceEntry {
+    /// Section of the configuration, like `Tracing`, `Project` for the fields of the service, or
+    /// the name of a registered feature.
+    pub section: &'static str,
+
+    /// Cargo feature of balthazar required by the value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feature: Option<&'static str>,
+
+    /// Environment variable, like `TRACING_LOG_LEVEL`.
+    pub env: Option<String>,
+
+    /// Command line flag, like `--tracing-log-level`.
+    pub flag: Option<String>,
+
+    /// Default values, joined by commas.
+    pub default: Option<String>,
+
+    /// Whether the value must be set.
+    pub required: bool,
+
+    /// Accepted values, when restricted.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub possible_values: Vec<String>,
+
+    pub description: Option<String>,
+}
+
+impl<T: Debug + Args> Config<T> {
+    /// Generates the reference of every configuration value, including the fields of `T` and the
+    /// features enabled by cargo features.
+    pub fn reference() -> ConfigReference {
+        Self::reference_with_features(&FeatureRegistry::new())
+    }
+
+    /// Generates the reference of every configuration value, including the fields of `T` and the
+    /// configuration of every registered feature.
+    pub fn reference_with_features(features: &FeatureRegistry) -> ConfigReference {
+        let sections = sections::<T>(features);
+        let mut command = features.augment_args(Config::<T>::command());
+        command.build();
+
+        let mut entries: Vec<(usize, ConfigReferenceEntry)> = command
+            .get_arguments()
+            .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+            .map(|arg| {
+                // nested configurations, like `LogOutputConfig` in `TracingConfig`, are also part
+                // of their parent, so the smallest section holding the argument is the right one
+                let section = sections
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, section)| {
+                        section
+                            .command
+                            .get_arguments()
+                            .any(|section_arg| section_arg.get_id() == arg.get_id())
+                    })
+                    .min_by_key(|(_, section)| section.command.get_arguments().count());
+                match section {
+                    Some((index, section)) => (
+                        index,
+                        ConfigReferenceEntry::new(section.name, section.feature, arg),
+                    ),
+                    None => (
+                        sections.len(),
+                        ConfigReferenceEntry::new("Other", None, arg),
+                    ),
+                }
+            })
+            .collect();
+        entries.sort_by_key(|(index, _)| *index);
+
+        let entries = entries.into_iter().map(|(_, entry)| entry).collect();
+        ConfigReference { entries }
+    }
+}
+
+impl ConfigReferenceEntry {
+    fn new(section: &'static str, feature: Option<&'static str>, arg: &Arg) -> Self {
+        let default: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect();
+        Self {
+            section,
+            feature,
+            env: arg.get_env().map(|env| env.to_string_lossy().into_owned()),
+            flag: arg.get_long().map(|long| format!("--{}", long)),
+            default: (!default.is_empty()).then(|| default.join(",")),
+            required: arg.is_required_set(),
+            possible_values: arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect(),
+            // clap removes the final period of single sentence doc comments
+            description: arg.get_help().map(|help| {
+                let help = help.to_string();
+                match help.ends_with('.') {
+                    true => help,
+                    false => format!("{}.", help),
+                }
+            }),
+        }
+    }
+}
+
+impl ConfigReference {
+    /// Renders the reference as Markdown tables, one per section.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::new();
+        let mut section = None;
+        for entry in &self.entries {
+            if section != Some((entry.section, entry.feature)) {
+                section = Some((entry.section, entry.feature));
+                if !markdown.is_empty() {
+                    markdown.push('\n');
+                }
+                match entry.feature {
+                    Some(feature) => {
+                        let _ =
+                            writeln!(markdown, "### {} (`{}` feature)\n", entry.section, feature);
+                    }
+                    None => {
+                        let _ = writeln!(markdown, "### {}\n", entry.section);
+                    }
+                }
+                markdown.push_str("| Variable | Flag | Default | Description |\n");
+                markdown.push_str("|----------|------|---------|-------------|\n");
+            }
+
+            let mut description = entry.description.clone().unwrap_or_default();
+            if entry.required {
+                description.push_str(" Required.");
+            }
+            if !entry.possible_values.is_empty() {
+                let _ = write!(
+                    description,
+                    " One of `{}`.",
+                    entry.possible_values.join("`, `")
+                );
+            }
+            let _ = writeln!(
+                markdown,
+                "| {} | {} | {} | {} |",
+                code_or_dash(entry.env.as_deref()),
+                code_or_dash(entry.flag.as_deref()),
+                code_or_dash(entry.default.as_deref()),
+                description.trim().replace('|', "\\|").replace('\n', " ")
+            );
+        }
+        markdown
+    }
+
+    /// Renders the reference as a JSON array of entries.
+    pub fn to_json(&self) -> crate::Result<String> {
+        Ok(serde_json::to_string_pretty(&self.entries)?)
+    }
+}
+
+fn code_or_dash(value: Option<&str>) -> String {
+    match value {
+        Some(value) if !value.is_empty() => format!("`{}`", value.replace('|', "\\|")),
+        _ => "-".to_string(),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Sections
+// -----------------------------------------------------------------------------
+struct Section {
+    name: &'static str,
+    feature: Option<&'static str>,
+    command: Command,
+}
+
+impl Section {
+    fn new<A: clap::Args>(name: &'static str, feature: Option<&'static str>) -> Self {
+        Self {
+            name,
+            feature,
+            command: A::augment_args(Command::new(name)),
+        }
+    }
+}
+
+/// Sections in the order they are documented, registered features last.
+fn sections<T: Args>(features: &FeatureRegistry) -> Vec<Section> {
+    let mut sections = vec![
+        Section::new::<T>("Project", None),
+        Section::new::<CoreConfig>("Core", None),
+        Section::new::<ConfigSourceConfig>("Configuration Sources", None),
+        Section::new::<TracingConfig>("Tracing", None),
+        Section::new::<LogOutputConfig>("Log Output", None),
+        Section::new::<ResourceConfig>("Resource", None),
+        Section::new::<TraceLinkConfig>("Trace Links", None),
+        #[cfg(feature = "sentry")]
+        Section::new::<crate::SentryConfig>("Sentry", Some("sentry")),
+        Section::new::<HttpServerConfig>("HTTP Server", None),
+    ];
+    sections.extend(features.sections().map(|(name, feature, command)| Section {
+        name,
+        feature,
+        command,
+    }));
+    sections
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use crate::{async_trait, Args, Config, Feature, FeatureRegistry, Features, Result};
+
+    #[derive(Debug, Args)]
+    struct Project {
+        /// Number of orders processed concurrently.
+        #[clap(
+            long = "reference-workers",
+            env = "REFERENCE_WORKERS",
+            default_value = "4"
+        )]
+        workers: u32,
+    }
+
+    #[test]
+    fn reference_of_project_and_environment() {
+        let reference = Config::<Project>::reference();
+
+        let workers = &reference.entries[0];
+        assert_eq!(workers.section, "Project");
+        assert_eq!(workers.env.as_deref(), Some("REFERENCE_WORKERS"));
+        assert_eq!(workers.flag.as_deref(), Some("--reference-workers"));
+        assert_eq!(workers.default.as_deref(), Some("4"));
+        assert_eq!(
+            workers.description.as_deref(),
+            Some("Number of orders processed concurrently.")
+        );
+
+        let format = reference
+            .entries
+            .iter()
+            .find(|entry| entry.env.as_deref() == Some("TRACING_FORMAT"))
+            .unwrap();
+        assert_eq!(format.section, "Tracing");
+        assert!(format.possible_values.contains(&"json".to_string()));
+
+        let output = reference
+            .entries
+            .iter()
+            .find(|entry| entry.env.as_deref() == Some("TRACING_OUTPUT"))
+            .unwrap();
+        assert_eq!(output.section, "Log Output");
+        assert!(reference
+            .entries
+            .iter()
+            .all(|entry| entry.section != "Other"));
+
+        let markdown = reference.to_markdown();
+        assert!(
+            markdown.starts_with("### Project\n\n| Variable | Flag | Default | Description |\n")
+        );
+        assert!(markdown.contains(
+            "| `REFERENCE_WORKERS` | `--reference-workers` | `4` | Number of orders processed concurrently. |\n"
+        ));
+        assert!(markdown.contains("### Tracing\n"));
+    }
+
+    #[derive(Debug, Args)]
+    struct LedgerConfig {
+        /// Address of the ledger service.
+        #[clap(long = "ledger-url", env = "LEDGER_URL")]
+        url: String,
+    }
+
+    struct Ledger;
+
+    #[async_trait]
+    impl Feature for Ledger {
+        type Config = LedgerConfig;
+        const NAME: &'static str = "ledger";
+        const TITLE: &'static str = "Ledger";
+
+        async fn init(_: &str, _: &LedgerConfig, _: &Features) -> Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn reference_of_registered_features() {
+        let features = FeatureRegistry::empty().register::<Ledger>();
+        let reference = Config::<Project>::reference_with_features(&features);
+
+        let ledger = reference.entries.last().unwrap();
+        assert_eq!(ledger.section, "Ledger");
+        assert_eq!(ledger.feature, None);
+        assert_eq!(ledger.env.as_deref(), Some("LEDGER_URL"));
+        assert!(ledger.required);
+        assert!(reference.to_markdown().contains("### Ledger\n"));
+    }
+
+    #[derive(Debug, Args)]
+    struct NoProject {}
+
+    #[test]
+    fn readme_contains_reference() {
+        let readme = include_str!("../README.md");
+        let markdown = Config::<NoProject>::reference().to_markdown();
+
+        // values of disabled cargo features are not generated, so only the lines that are must be
+        // in the README, unless every cargo feature is enabled
+        let missing: Vec<&str> = markdown
+            .lines()
+            .filter(|line| !readme.lines().any(|readme_line| readme_line == *line))
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "README.md is missing lines of the reference:\n{}",
+            missing.join("\n")
+        );
+        if cfg!(all(
+            feature = "postgres",
+            feature = "redis",
+            feature = "sentry",
+            feature = "streaming"
+        )) {
+            let start = readme.find("\n### Core\n").unwrap() + 1;
+            assert_eq!(readme[start..], markdown);
+        }
+    }
+
+    #[cfg(feature = "sentry")]
+    #[test]
+    fn reference_of_feature_gated_values() {
+        let reference = Config::<Project>::reference();
+
+        let dsn = reference
+            .entries
+            .iter()
+            .find(|entry| entry.env.as_deref() == Some("SENTRY_DSN"))
+            .unwrap();
+        assert_eq!(dsn.feature, Some("sentry"));
+        assert!(reference
+            .to_markdown()
+            .contains("### Sentry (`sentry` feature)\n"));
+    }
+}
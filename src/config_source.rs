@@ -0,0 +1,609 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command, FromArgMatches, Subcommand};
+use eyre::WrapErr;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config_command::ConfigCommand;
+use crate::lang::sensitive::{scrub, MASK};
+use crate::{throw, Parser, Result, Sensitive};
+
+const CONFIG_FILE_ID: &str = "config_file";
+const ENV_FILES_ID: &str = "env_files";
+
+// -----------------------------------------------------------------------------
+// Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Parser)]
+pub struct ConfigSourceConfig {
+    /// TOML, YAML or JSON file with configuration values, used when neither a flag nor an
+    /// environment variable sets them.
+    #[clap(long = "config", env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
+    /// Comma-separated `.env` files with configuration values, taking precedence over the
+    /// configuration file. Files listed later take precedence, and missing files are ignored.
+    #[clap(
+        long = "env-file",
+        env = "CONFIG_ENV_FILES",
+        value_delimiter = ',',
+        default_value = ".env"
+    )]
+    pub env_files: Vec<PathBuf>,
+
+    /// Whether the configuration file and `.env` files are read again when they change or on
+    /// `SIGHUP`, publishing the new project configuration.
+    #[clap(
+        long = "config-watch",
+        env = "CONFIG_WATCH",
+        default_value_t = false,
+        action = ArgAction::Set
+    )]
+    pub config_watch: bool,
+
+    /// Seconds between checks for changes of the configuration file and `.env` files.
+    #[clap(
+        long = "config-watch-interval-secs",
+        env = "CONFIG_WATCH_INTERVAL_SECS",
+        default_value = "10"
+    )]
+    pub config_watch_interval_secs: u64,
+}
+
+impl ConfigSourceConfig {
+    /// Configuration file and `.env` files, which may not exist.
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        self.config_file
+            .iter()
+            .chain(&self.env_files)
+            .cloned()
+            .collect()
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Sources
+// -----------------------------------------------------------------------------
+
+/// Configuration parsed from every source, along with the matched values of its arguments.
+#[derive(Debug)]
+pub(crate) struct LoadedConfig<C> {
+    pub config: C,
+    pub command: Option<ConfigCommand>,
+    pub matches: ArgMatches,
+    pub layers: ConfigLayers,
+
+    /// Arguments the configuration was parsed with, including the ones of registered features.
+    pub arguments: Command,
+}
+
+impl<C: FromArgMatches> LoadedConfig<C> {
+    /// Parses the configuration from the given arguments, environment and configuration files.
+    ///
+    /// Invalid arguments are returned as a `clap::Error`, which can print the usage and exit.
+    pub(crate) fn load(arguments: Command, args: &[OsString]) -> Result<Self> {
+        Self::load_with_env(arguments, args, |name| std::env::var(name).ok())
+    }
+
+    /// Parses the configuration like [`LoadedConfig::load`], reading environment variables with
+    /// the given lookup.
+    pub(crate) fn load_with_env(
+        arguments: Command,
+        args: &[OsString],
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let command = ConfigCommand::augment_subcommands(arguments.clone());
+        let layers = ConfigLayers::load(&command, args, env)?;
+        let args = layers.apply(&command, args);
+        let matches = command.try_get_matches_from(args)?;
+        let config = C::from_arg_matches(&matches)?;
+        let command = match matches.subcommand() {
+            Some(_) => Some(ConfigCommand::from_arg_matches(&matches)?),
+            None => None,
+        };
+        Ok(Self {
+            config,
+            command,
+            matches,
+            layers,
+            arguments,
+        })
+    }
+
+    /// Source of the value of an argument.
+    pub(crate) fn source(&self, id: &str) -> ConfigValueSource {
+        if let Some(layer) = self.layers.values.get(id) {
+            return layer.source.clone();
+        }
+        match self.matches.value_source(id) {
+            Some(ValueSource::CommandLine) => ConfigValueSource::CommandLine,
+            Some(ValueSource::EnvVariable) => ConfigValueSource::Environment,
+            _ => ConfigValueSource::Default,
+        }
+    }
+}
+
+/// Where the value of an argument comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub(crate) enum ConfigValueSource {
+    CommandLine,
+    Environment,
+    EnvFile { path: PathBuf },
+    ConfigFile { path: PathBuf },
+    Default,
+}
+
+#[derive(Debug, Clone)]
+struct LayerValue {
+    values: Vec<String>,
+    source: ConfigValueSource,
+}
+
+/// Values of environment variables, the configuration file and `.env` files, keyed by argument id.
+///
+/// They are passed as flags before the given arguments, and only for arguments not already set
+/// by a flag, so clap parses and validates them like any other value. Setting them as default
+/// values instead would validate them in debug assertions, panicking.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConfigLayers {
+    values: BTreeMap<String, LayerValue>,
+}
+
+impl ConfigLayers {
+    /// Reads environment variables with the given lookup, and the configuration file and `.env`
+    /// files selected by the command line or environment.
+    pub(crate) fn load(
+        command: &Command,
+        args: &[OsString],
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        // parsed leniently, because required values may only be set by the files, but arguments
+        // clap cannot parse at all, like `--help`, are reported
+        let lenient = command.clone().ignore_errors(true);
+        let flags = lenient.clone().try_get_matches_from(args)?;
+        let set_by_flag = |id: &String| flags.value_source(id) == Some(ValueSource::CommandLine);
+
+        let mut environment = Self::environment(command, env);
+        environment.values.retain(|id, _| !set_by_flag(id));
+        let selected = lenient.try_get_matches_from(environment.apply(command, args))?;
+        let config_file = selected.get_one::<PathBuf>(CONFIG_FILE_ID).cloned();
+        let env_files: Vec<PathBuf> = selected
+            .get_many::<PathBuf>(ENV_FILES_ID)
+            .map(|files| files.cloned().collect())
+            .unwrap_or_default();
+
+        let keys = ArgKeys::new(command);
+        let mut layers = Self::default();
+        if let Some(path) = config_file {
+            for (key, values) in read_config_file(&path)? {
+                let id = keys
+                    .find(&key)
+                    .ok_or_else(|| throw!("Unknown key `{}` in {}", key, path.display()))?;
+                let source = ConfigValueSource::ConfigFile { path: path.clone() };
+                layers.insert(id, values, source);
+            }
+        }
+        for path in env_files.into_iter().filter(|path| path.exists()) {
+            // unrelated variables are common in `.env` files, so they are ignored
+            for (key, value) in read_env_file(&path)? {
+                if let Some(id) = keys.find(&key) {
+                    let source = ConfigValueSource::EnvFile { path: path.clone() };
+                    layers.insert(id, vec![value], source);
+                }
+            }
+        }
+        layers.values.extend(environment.values);
+
+        layers.values.retain(|id, _| !set_by_flag(id));
+        Ok(layers)
+    }
+
+    /// Values of the environment variables of arguments that can be passed as flags.
+    fn environment(command: &Command, env: impl Fn(&str) -> Option<String>) -> Self {
+        let mut layer = Self::default();
+        for arg in command
+            .get_arguments()
+            .filter(|arg| arg.get_long().is_some())
+        {
+            let Some(name) = arg.get_env() else {
+                continue;
+            };
+            // like clap, empty variables are not set
+            if let Some(value) = env(&name.to_string_lossy()).filter(|value| !value.is_empty()) {
+                layer.insert(
+                    arg.get_id().as_str(),
+                    vec![value],
+                    ConfigValueSource::Environment,
+                );
+            }
+        }
+        layer
+    }
+
+    fn insert(&mut self, id: &str, values: Vec<String>, source: ConfigValueSource) {
+        self.values
+            .insert(id.to_string(), LayerValue { values, source });
+    }
+
+    /// Adds the values as flags after the program name.
+    pub(crate) fn apply(&self, command: &Command, args: &[OsString]) -> Vec<OsString> {
+        let mut flags = Vec::new();
+        for arg in command.get_arguments() {
+            let (Some(LayerValue { values, .. }), Some(long)) =
+                (self.values.get(arg.get_id().as_str()), arg.get_long())
+            else {
+                continue;
+            };
+            match arg.get_action() {
+                ArgAction::SetTrue if values.iter().all(|value| is_true(value)) => {
+                    flags.push(format!("--{}", long).into())
+                }
+                ArgAction::SetFalse if values.iter().all(|value| !is_true(value)) => {
+                    flags.push(format!("--{}", long).into())
+                }
+                ArgAction::SetTrue | ArgAction::SetFalse => {}
+                _ => flags.extend(
+                    values
+                        .iter()
+                        .map(|value| format!("--{}={}", long, value).into()),
+                ),
+            }
+        }
+
+        let mut args = args.iter().cloned();
+        args.next().into_iter().chain(flags).chain(args).collect()
+    }
+}
+
+/// Whether a value of a flag is true, like clap parses environment variables of flags.
+fn is_true(value: &str) -> bool {
+    !matches!(
+        value.to_ascii_lowercase().as_str(),
+        "" | "n" | "no" | "f" | "false" | "off" | "0"
+    )
+}
+
+/// Matches keys of configuration files to argument ids.
+struct ArgKeys(BTreeMap<String, String>);
+
+impl ArgKeys {
+    fn new(command: &Command) -> Self {
+        let mut keys = BTreeMap::new();
+        for arg in command.get_arguments() {
+            let id = arg.get_id().as_str();
+            // values are passed as flags, so positional arguments cannot be set by files
+            if id == CONFIG_FILE_ID || id == ENV_FILES_ID || arg.get_long().is_none() {
+                continue;
+            }
+            let names = [
+                Some(id.to_string()),
+                arg.get_long().map(str::to_string),
+                arg.get_env().map(|env| env.to_string_lossy().into_owned()),
+            ];
+            for name in names.into_iter().flatten() {
+                keys.insert(normalize_key(&name), id.to_string());
+            }
+        }
+        Self(keys)
+    }
+
+    fn find(&self, key: &str) -> Option<&str> {
+        self.0.get(&normalize_key(key)).map(String::as_str)
+    }
+}
+
+/// `tracing.sample-rate`, `tracing_sample_rate` and `TRACING_SAMPLE_RATE` are the same key.
+fn normalize_key(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            '-' | '.' => '_',
+            c => c.to_ascii_uppercase(),
+        })
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+// Values
+// -----------------------------------------------------------------------------
+
+/// Keys of the arguments shown to users, which are their environment variable when they have one.
+pub(crate) fn arg_keys(command: &Command) -> BTreeMap<String, String> {
+    command
+        .get_arguments()
+        .map(|arg| {
+            let key = match (arg.get_env(), arg.get_long()) {
+                (Some(env), _) => env.to_string_lossy().into_owned(),
+                (None, Some(long)) => format!("--{}", long),
+                (None, None) => arg.get_id().to_string(),
+            };
+            (arg.get_id().to_string(), key)
+        })
+        .collect()
+}
+
+/// Values of an argument as given in the configuration sources, joined by commas.
+pub(crate) fn raw_value(matches: &ArgMatches, id: &str) -> Option<String> {
+    let values: Vec<String> = matches
+        .try_get_raw(id)
+        .ok()
+        .flatten()?
+        .map(|value| value.to_string_lossy().into_owned())
+        .collect();
+    Some(values.join(","))
+}
+
+/// Value of an argument safe to show: `Sensitive<String>` arguments are always masked, and other
+/// values are scrubbed of the values of `Sensitive` arguments of any type.
+pub(crate) fn masked_value(matches: &ArgMatches, id: &str) -> Option<String> {
+    let value = raw_value(matches, id)?;
+    if is_sensitive(matches, id) {
+        Some(MASK.to_string())
+    } else {
+        Some(scrub(&value).into_owned())
+    }
+}
+
+fn is_sensitive(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.try_get_one::<Sensitive<String>>(id), Ok(Some(_)))
+}
+
+// -----------------------------------------------------------------------------
+// Files
+// -----------------------------------------------------------------------------
+
+/// Reads a configuration file, whose format is given by its extension, flattening nested tables
+/// into keys joined by `_`, like `POSTGRES_URL` for `url` in the `postgres` table.
+fn read_config_file(path: &Path) -> Result<Vec<(String, Vec<String>)>> {
+    let content = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let value: Value = match extension {
+        Some("toml") => toml::from_str(&content).wrap_err("Invalid TOML config file")?,
+        Some("yaml" | "yml") => {
+            serde_yaml::from_str(&content).wrap_err("Invalid YAML config file")?
+        }
+        Some("json") => serde_json::from_str(&content).wrap_err("Invalid JSON config file")?,
+        _ => return Err(throw!("Unsupported config file format: {}", path.display())),
+    };
+
+    let mut entries = Vec::new();
+    flatten(None, value, &mut entries)
+        .wrap_err_with(|| format!("Invalid config file {}", path.display()))?;
+    Ok(entries)
+}
+
+fn flatten(
+    key: Option<String>,
+    value: Value,
+    entries: &mut Vec<(String, Vec<String>)>,
+) -> Result<()> {
+    match (key, value) {
+        (key, Value::Object(fields)) => {
+            for (field, value) in fields {
+                let key = match &key {
+                    Some(key) => format!("{}_{}", key, field),
+                    None => field,
+                };
+                flatten(Some(key), value, entries)?;
+            }
+        }
+        (Some(_), Value::Null) => {}
+        (Some(key), Value::Array(items)) => {
+            let values = items
+                .into_iter()
+                .map(|item| scalar(&key, item))
+                .collect::<Result<_>>()?;
+            entries.push((key, values));
+        }
+        (Some(key), value) => {
+            let value = scalar(&key, value)?;
+            entries.push((key, vec![value]));
+        }
+        (None, _) => return Err(throw!("Expected a table of configuration values")),
+    }
+    Ok(())
+}
+
+fn scalar(key: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value),
+        Value::Bool(value) => Ok(value.to_string()),
+        Value::Number(value) => Ok(value.to_string()),
+        _ => Err(throw!("Expected a string, number or boolean for `{}`", key)),
+    }
+}
+
+/// Reads the variables of a `.env` file, without setting them in the process environment.
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    dotenvy::from_path_iter(path)
+        .and_then(|variables| variables.collect())
+        .wrap_err_with(|| format!("Invalid env file {}", path.display()))
+}
+
+/// File in the temporary directory, deleted when dropped.
+#[cfg(test)]
+pub(crate) struct TempFile(PathBuf);
+
+#[cfg(test)]
+impl TempFile {
+    pub(crate) fn new(name: &str, content: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("balthazar-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use clap::CommandFactory;
+
+    use crate::{Args, Config};
+
+    use super::{read_config_file, ArgKeys, ConfigValueSource, LoadedConfig, TempFile};
+
+    #[derive(Debug, Args)]
+    struct Project {
+        #[clap(long = "layers-file", env = "LAYERS_TEST_FILE")]
+        file: String,
+
+        #[clap(long = "layers-env-file", env = "LAYERS_TEST_ENV_FILE")]
+        env_file: String,
+
+        #[clap(long = "layers-env", env = "LAYERS_TEST_ENV")]
+        env: String,
+
+        #[clap(long = "layers-cli", env = "LAYERS_TEST_CLI")]
+        cli: String,
+
+        #[clap(
+            long = "layers-default",
+            env = "LAYERS_TEST_DEFAULT",
+            default_value = "default"
+        )]
+        default: String,
+
+        #[clap(long = "layers-list", env = "LAYERS_TEST_LIST", value_delimiter = ',')]
+        list: Vec<u32>,
+    }
+
+    #[test]
+    fn flatten_nested_config_files() {
+        let toml = TempFile::new(
+            "nested.toml",
+            "[tracing]\nsample-rate = 0.5\nformat = \"json\"\n[postgres]\nurl = \"postgres://db\"\n",
+        );
+        let yaml = TempFile::new(
+            "nested.yaml",
+            "tracing:\n  sample-rate: 0.5\n  format: json\npostgres:\n  url: postgres://db\n",
+        );
+        let json = TempFile::new(
+            "nested.json",
+            r#"{"tracing": {"sample-rate": 0.5, "format": "json"}, "postgres": {"url": "postgres://db"}}"#,
+        );
+
+        for file in [toml, yaml, json] {
+            let path = file.path();
+            let mut entries = read_config_file(path).unwrap();
+            entries.sort();
+            assert_eq!(
+                entries,
+                vec![
+                    (
+                        "postgres_url".to_string(),
+                        vec!["postgres://db".to_string()]
+                    ),
+                    ("tracing_format".to_string(), vec!["json".to_string()]),
+                    ("tracing_sample-rate".to_string(), vec!["0.5".to_string()]),
+                ],
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn layered_config_precedence() {
+        let config_file = TempFile::new(
+            "layers.toml",
+            "[layers_test]\nfile = \"file\"\nenv_file = \"file\"\nenv = \"file\"\ncli = \"file\"\nlist = [1, 2]\n",
+        );
+        let env_file = TempFile::new(
+            "layers.env",
+            "LAYERS_TEST_ENV_FILE=env-file\nLAYERS_TEST_ENV=env-file\nLAYERS_TEST_CLI=env-file\nUNRELATED=1\n",
+        );
+        let env = BTreeMap::from([
+            ("LAYERS_TEST_ENV", "env"),
+            ("LAYERS_TEST_CLI", "env"),
+            ("LAYERS_TEST_DEFAULT", ""),
+        ]);
+
+        let loaded = LoadedConfig::<Config<Project>>::load_with_env(
+            Config::<Project>::command(),
+            &[
+                "test".into(),
+                "--layers-cli".into(),
+                "cli".into(),
+                "--config".into(),
+                config_file.path().into(),
+                "--env-file".into(),
+                env_file.path().into(),
+            ],
+            |name| env.get(name).map(|value| value.to_string()),
+        )
+        .unwrap();
+        assert_eq!(loaded.source("env"), ConfigValueSource::Environment);
+        assert_eq!(loaded.source("cli"), ConfigValueSource::CommandLine);
+        let project = loaded.config.project;
+
+        assert_eq!(project.file, "file");
+        assert_eq!(project.env_file, "env-file");
+        assert_eq!(project.env, "env");
+        assert_eq!(project.cli, "cli");
+        assert_eq!(project.default, "default");
+        assert_eq!(project.list, vec![1, 2]);
+    }
+
+    #[test]
+    fn report_config_file_of_environment_and_unparsable_arguments() {
+        let config_file = TempFile::new("layers-env.toml", "[layers_test]\nfile = \"file\"\n");
+        let path = config_file.path().to_string_lossy().into_owned();
+        let env = BTreeMap::from([("CONFIG_FILE", path.as_str())]);
+        let load = |args: &[&str]| {
+            let args: Vec<_> = args.iter().map(Into::into).collect();
+            LoadedConfig::<Config<Project>>::load_with_env(
+                Config::<Project>::command(),
+                &args,
+                |name| env.get(name).map(|value| value.to_string()),
+            )
+        };
+
+        // the configuration file selected by the environment is read, so only the required values
+        // it does not set are missing
+        let error = load(&["test"]).unwrap_err();
+        let error = error.downcast_ref::<clap::Error>().unwrap().to_string();
+        let missing = error.split("Usage:").next().unwrap();
+        assert!(missing.contains("--layers-env-file"));
+        assert!(!missing.contains("--layers-file"));
+
+        let error = load(&["test", "--help"]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<clap::Error>().unwrap().kind(),
+            clap::error::ErrorKind::DisplayHelp
+        );
+    }
+
+    #[test]
+    fn match_keys_to_arguments() {
+        let keys = ArgKeys::new(&Config::<Project>::command());
+
+        assert_eq!(keys.find("LAYERS_TEST_FILE"), Some("file"));
+        assert_eq!(keys.find("layers-env-file"), Some("env_file"));
+        assert_eq!(keys.find("tracing_sample-rate"), Some("sample_rate"));
+        assert_eq!(keys.find("postgres_pool_size"), None);
+        assert_eq!(keys.find("typo"), None);
+    }
+}
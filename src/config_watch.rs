@@ -0,0 +1,310 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fmt::Debug,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{ArgMatches, Command};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use crate::config_source::{arg_keys, masked_value, raw_value, LoadedConfig};
+use crate::{as_json, Args, Config, Result};
+
+// -----------------------------------------------------------------------------
+// Watcher
+// -----------------------------------------------------------------------------
+
+/// Publishes the project configuration, read again when its files change or on `SIGHUP` when
+/// `CONFIG_WATCH` is enabled.
+///
+/// Must be called within a Tokio runtime when watching is enabled.
+pub(crate) fn watch_project<T>(
+    args: Vec<OsString>,
+    loaded: &LoadedConfig<Config<T>>,
+) -> Result<watch::Receiver<Arc<T>>>
+where
+    T: Debug + Args + Send + Sync + 'static,
+{
+    let project = T::from_arg_matches(&loaded.matches)?;
+    let (sender, receiver) = watch::channel(Arc::new(project));
+
+    let source = &loaded.config.environment.source;
+    if source.config_watch {
+        let files = source.files();
+        let watcher = ConfigWatcher {
+            args,
+            arguments: loaded.arguments.clone(),
+            matches: loaded.matches.clone(),
+            fingerprint: fingerprint(&files),
+            files,
+            keys: arg_keys(&loaded.arguments),
+            project_ids: project_ids::<T>(),
+            sender,
+        };
+        let interval = Duration::from_secs(source.config_watch_interval_secs);
+        tokio::spawn(watcher.run(interval));
+    }
+    Ok(receiver)
+}
+
+struct ConfigWatcher<T> {
+    args: Vec<OsString>,
+    arguments: Command,
+    matches: ArgMatches,
+    files: Vec<PathBuf>,
+    fingerprint: Vec<Option<Vec<u8>>>,
+    keys: BTreeMap<String, String>,
+    project_ids: BTreeSet<String>,
+    sender: watch::Sender<Arc<T>>,
+}
+
+impl<T> ConfigWatcher<T>
+where
+    T: Debug + Args + Send + Sync + 'static,
+{
+    async fn run(mut self, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut hangup = Hangup::new();
+
+        loop {
+            let forced = tokio::select! {
+                _ = ticks.tick() => false,
+                _ = hangup.recv() => true,
+            };
+            if self.sender.is_closed() {
+                return;
+            }
+
+            // files are compared rather than their modification time, because Kubernetes updates
+            // mounted config maps by swapping a symbolic link
+            let fingerprint = fingerprint(&self.files);
+            if !forced && fingerprint == self.fingerprint {
+                continue;
+            }
+            self.fingerprint = fingerprint;
+            self.reload(forced);
+        }
+    }
+
+    /// Parses the configuration again, keeping the current one when it is not valid.
+    fn reload(&mut self, forced: bool) {
+        let loaded = match LoadedConfig::<Config<T>>::load(self.arguments.clone(), &self.args) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                tracing::error!(reason = ?e, "failed to reload configuration, keeping the current one");
+                return;
+            }
+        };
+
+        let (changes, ignored): (Vec<_>, Vec<_>) =
+            changes(&self.keys, &self.matches, &loaded.matches)
+                .into_iter()
+                .partition(|change| self.project_ids.contains(&change.id));
+        if !ignored.is_empty() {
+            tracing::warn!(
+                changes = %as_json(&ignored),
+                "configuration changes outside of the project only apply after a restart"
+            );
+        }
+        self.matches = loaded.matches;
+
+        // references to secrets may resolve to new values without any visible change
+        if changes.is_empty() && !forced {
+            return;
+        }
+        tracing::info!(changes = %as_json(&changes), "reloaded configuration");
+        self.sender.send_replace(Arc::new(loaded.config.project));
+    }
+}
+
+/// Completes on every `SIGHUP`, and never on platforms without it.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        let signal = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                tracing::error!(reason = ?e, "failed to listen to hangup signal");
+                None
+            }
+        };
+        Self {
+            #[cfg(unix)]
+            signal,
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        futures_util::future::pending::<()>().await;
+    }
+}
+
+fn fingerprint(files: &[PathBuf]) -> Vec<Option<Vec<u8>>> {
+    files.iter().map(|file| fs::read(file).ok()).collect()
+}
+
+fn project_ids<T: Args>() -> BTreeSet<String> {
+    T::augment_args(Command::new("project"))
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+// Changes
+// -----------------------------------------------------------------------------
+
+/// Change of a configuration value, with `Sensitive` values masked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct ConfigChange {
+    #[serde(skip)]
+    id: String,
+    key: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+/// Compares the values of every argument, as given in the configuration sources.
+fn changes(
+    keys: &BTreeMap<String, String>,
+    old: &ArgMatches,
+    new: &ArgMatches,
+) -> Vec<ConfigChange> {
+    let ids: BTreeSet<&str> = old.ids().chain(new.ids()).map(|id| id.as_str()).collect();
+    ids.into_iter()
+        .filter_map(|id| {
+            let old_value = raw_value(old, id);
+            let new_value = raw_value(new, id);
+            if old_value == new_value {
+                return None;
+            }
+            Some(ConfigChange {
+                id: id.to_string(),
+                key: keys.get(id).cloned().unwrap_or_else(|| id.to_string()),
+                old: masked_value(old, id),
+                new: masked_value(new, id),
+            })
+        })
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use clap::CommandFactory;
+    use tokio::sync::watch;
+
+    use crate::config_source::{LoadedConfig, TempFile};
+    use crate::error_reporting::install_test_hooks;
+    use crate::lang::sensitive::MASK;
+    use crate::{Args, Config, Sensitive};
+
+    use crate::config_source::arg_keys;
+
+    use super::{changes, fingerprint, project_ids, ConfigWatcher};
+
+    #[derive(Debug, Args)]
+    struct Project {
+        #[clap(long = "watch-limit", env = "WATCH_TEST_LIMIT")]
+        limit: u32,
+
+        #[clap(long = "watch-token", env = "WATCH_TEST_TOKEN")]
+        token: Sensitive<String>,
+    }
+
+    #[tokio::test]
+    async fn reload_project_config() {
+        install_test_hooks();
+        let temp_file = TempFile::new(
+            "watch.yaml",
+            "watch_test_limit: 10\nwatch_test_token: first-watch-token\ntracing_log_level: info\n",
+        );
+        let config_file = temp_file.path().to_path_buf();
+        let args = vec!["test".into(), "--config".into(), config_file.clone().into()];
+        let loaded =
+            LoadedConfig::<Config<Project>>::load(Config::<Project>::command(), &args).unwrap();
+
+        let (sender, mut receiver) = watch::channel(Arc::new(loaded.config.project));
+        let files = vec![config_file.clone()];
+        let mut watcher = ConfigWatcher {
+            args,
+            arguments: loaded.arguments,
+            matches: loaded.matches,
+            fingerprint: fingerprint(&files),
+            files,
+            keys: arg_keys(&Config::<Project>::command()),
+            project_ids: project_ids::<Project>(),
+            sender,
+        };
+
+        // invalid values keep the current configuration
+        fs::write(&config_file, "watch_test_limit: many\n").unwrap();
+        watcher.reload(false);
+        assert!(!receiver.has_changed().unwrap());
+
+        fs::write(
+            &config_file,
+            "watch_test_limit: 20\nwatch_test_token: second-watch-token\ntracing_log_level: debug\n",
+        )
+        .unwrap();
+        watcher.reload(false);
+        assert!(receiver.has_changed().unwrap());
+        let project = receiver.borrow_and_update().clone();
+        assert_eq!(project.limit, 20);
+        assert_eq!(project.token.as_str(), "second-watch-token");
+    }
+
+    #[test]
+    fn changes_mask_sensitive_values() {
+        let old = LoadedConfig::<Config<Project>>::load(
+            Config::<Project>::command(),
+            &[
+                "test".into(),
+                "--watch-limit=1".into(),
+                "--watch-token=old-diff-token".into(),
+            ],
+        )
+        .unwrap();
+        let new = LoadedConfig::<Config<Project>>::load(
+            Config::<Project>::command(),
+            &[
+                "test".into(),
+                "--watch-limit=2".into(),
+                "--watch-token=new-diff-token".into(),
+            ],
+        )
+        .unwrap();
+
+        let keys = arg_keys(&Config::<Project>::command());
+        let changes = changes(&keys, &old.matches, &new.matches);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].key, "WATCH_TEST_LIMIT");
+        assert_eq!(changes[0].old.as_deref(), Some("1"));
+        assert_eq!(changes[0].new.as_deref(), Some("2"));
+        assert_eq!(changes[1].key, "WATCH_TEST_TOKEN");
+        assert_eq!(changes[1].old.as_deref(), Some(MASK));
+        assert_eq!(changes[1].new.as_deref(), Some(MASK));
+    }
+}
@@ -1,19 +1,52 @@
+use std::{panic, time::Duration};
+
+use color_eyre::config::{HookBuilder, Theme};
+
+use crate::error_reporting::report_panic;
+use crate::trace::flush_telemetry;
 use crate::*;
 
+/// Maximum time an aborting panic waits for buffered spans to be exported.
+const PANIC_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, Parser)]
 pub struct CoreConfig {
+    /// Disables colors of reports and formatted events.
     #[clap(short, long, env = "NO_COLOR")]
     pub no_color: bool,
 }
 
 pub struct Core;
 
-#[crate::async_trait]
-impl Feature for Core {
-    async fn init(_service_name: &str, config: &EnvironmentConfig) -> Result<Self> {
-        if !config.core.no_color {
-            color_eyre::install()?;
-        }
+impl Core {
+    /// Installs the error report and panic hooks.
+    pub async fn init(_service_name: &str, config: &EnvironmentConfig) -> Result<Self> {
+        // installed without colors too, so reports capture span traces for `report_error`
+        let theme = if config.core.no_color {
+            Theme::new()
+        } else {
+            Theme::dark()
+        };
+        let (panic_hook, eyre_hook) = HookBuilder::default().theme(theme).into_hooks();
+        eyre_hook.install()?;
+
+        // panics are logged through tracing, so JSON pipelines receive them structured, while
+        // text formats also print the readable report of `color_eyre`
+        let print_report = !matches!(
+            config.tracing.format,
+            TracingFormat::Json | TracingFormat::JsonPretty
+        );
+        panic::set_hook(Box::new(move |info| {
+            report_panic(info.payload(), info.location());
+            if print_report {
+                eprintln!("{}", panic_hook.panic_report(info));
+            }
+            // unwinding panics are caught by tokio tasks, or drop `Tracing` and its guards when
+            // the main thread goes down, so only an aborting process must be flushed here
+            if cfg!(panic = "abort") {
+                flush_telemetry(PANIC_FLUSH_TIMEOUT);
+            }
+        }));
 
         Ok(Self)
     }
@@ -0,0 +1,278 @@
+use std::{
+    any::{type_name, Any},
+    backtrace::Backtrace,
+    error::Error,
+    panic::Location,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing_error::SpanTrace;
+
+use crate::as_json;
+
+/// Event field holding an [`ErrorReport`] as JSON, recorded by [`report_error`].
+pub(crate) const ERROR_REPORT_FIELD: &str = "error.report";
+
+/// Kind of the errors reported for panics.
+pub(crate) const PANIC_KIND: &str = "panic";
+
+// -----------------------------------------------------------------------------
+// Error Report
+// -----------------------------------------------------------------------------
+
+/// Structured representation of an error and its causes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ErrorReport {
+    /// Type name of the root cause, like `core::num::error::ParseIntError`, see [`error_kind`].
+    pub kind: String,
+
+    /// Messages of the error and its causes, from the outermost to the root cause.
+    pub chain: Vec<String>,
+
+    /// Backtrace captured when the error was created, if enabled by `RUST_BACKTRACE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option<String>,
+
+    /// Spans entered when the error was created, from the innermost.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub span_trace: Vec<SpanTraceFrame>,
+
+    /// Report as rendered by `color_eyre`, including custom sections like notes and suggestions,
+    /// which are not accessible individually.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SpanTraceFrame {
+    pub name: String,
+    pub target: String,
+    pub fields: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl ErrorReport {
+    pub(crate) fn from_report(report: &eyre::Report) -> Self {
+        let handler = report.handler().downcast_ref::<color_eyre::Handler>();
+        Self {
+            kind: error_kind(report.root_cause()),
+            chain: report.chain().map(ToString::to_string).collect(),
+            backtrace: handler
+                .and_then(|handler| handler.backtrace())
+                .map(|backtrace| format!("{:?}", backtrace)),
+            span_trace: handler
+                .and_then(|handler| handler.span_trace())
+                .map(span_trace_frames)
+                .unwrap_or_default(),
+            report: Some(strip_ansi(&format!("{:?}", report))),
+        }
+    }
+
+    pub(crate) fn from_error(error: &(dyn Error + 'static)) -> Self {
+        let chain: Vec<&(dyn Error + 'static)> =
+            std::iter::successors(Some(error), |&error| error.source()).collect();
+        Self {
+            kind: error_kind(chain[chain.len() - 1]),
+            chain: chain.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Logs an error with its cause chain, span trace and backtrace.
+///
+/// JSON formatted events get an `error` object with the `kind`, `chain` and `backtrace` of the
+/// error. When the `sentry` feature is enabled, it is reported to Sentry as an exception with the
+/// span trace and the rendered report.
+///
+/// Reports are not reported by themselves when propagated with `?`: call it where the error is
+/// handled, or record the error as a `&dyn Error` field, which only gets its `kind` and `chain`.
+pub fn report_error(report: &eyre::Report) {
+    let error = ErrorReport::from_report(report);
+    tracing::error!(error.report = %as_json(&error), "{:#}", report);
+}
+
+/// Logs a panic with its message, location and backtrace, like [`report_error`].
+pub(crate) fn report_panic(payload: &(dyn Any + Send), location: Option<&Location<'_>>) {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let location = location
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "<unknown>".to_string());
+
+    let error = ErrorReport {
+        kind: PANIC_KIND.to_string(),
+        chain: vec![message.clone()],
+        backtrace: Some(Backtrace::force_capture().to_string()),
+        ..Default::default()
+    };
+    tracing::error!(
+        error.report = %as_json(&error),
+        panic.location = %location,
+        "panicked at {}: {}",
+        location,
+        message
+    );
+}
+
+/// Type name of the error, when it is one of the common error types it can be downcast to.
+///
+/// The type of other errors is erased, so the leading type or variant name of their `Debug`
+/// representation is used instead.
+fn error_kind(error: &(dyn Error + 'static)) -> String {
+    macro_rules! downcast_kind {
+        ($($(#[$meta:meta])* $ty:ty),* $(,)?) => {
+            $(
+                $(#[$meta])*
+                if error.is::<$ty>() {
+                    return type_name::<$ty>().to_string();
+                }
+            )*
+        };
+    }
+    downcast_kind!(
+        std::io::Error,
+        std::fmt::Error,
+        std::num::ParseIntError,
+        std::num::ParseFloatError,
+        std::str::ParseBoolError,
+        std::str::Utf8Error,
+        std::string::FromUtf8Error,
+        std::net::AddrParseError,
+        serde_json::Error,
+        reqwest::Error,
+        tokio::task::JoinError,
+        tokio::time::error::Elapsed,
+        #[cfg(feature = "postgres")]
+        sqlx::Error,
+        #[cfg(feature = "redis")]
+        bb8_redis::redis::RedisError,
+    );
+
+    let kind: String = format!("{:?}", error)
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == ':')
+        .collect();
+    if kind.is_empty() {
+        "Error".to_string()
+    } else {
+        kind
+    }
+}
+
+fn span_trace_frames(span_trace: &SpanTrace) -> Vec<SpanTraceFrame> {
+    let mut frames = Vec::new();
+    span_trace.with_spans(|metadata, fields| {
+        frames.push(SpanTraceFrame {
+            name: metadata.name().to_string(),
+            target: metadata.target().to_string(),
+            fields: fields.to_string(),
+            file: metadata.file().map(str::to_string),
+            line: metadata.line(),
+        });
+        true
+    });
+    frames
+}
+
+/// Removes ANSI escape sequences added by the `color_eyre` theme.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // skip the sequence up to its final letter, like `m` in `ESC[31m`
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+
+/// Installs `color_eyre` once for every test, before any report is created, so reports capture
+/// span traces instead of using the default handler of `eyre`.
+#[cfg(test)]
+pub(crate) fn install_test_hooks() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| color_eyre::install().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::WrapErr;
+    use tracing_error::ErrorLayer;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::{error_kind, install_test_hooks, strip_ansi, ErrorReport};
+
+    #[test]
+    fn error_report_from_eyre_report() {
+        install_test_hooks();
+        let subscriber = Registry::default().with(ErrorLayer::default());
+        let report = tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("load_order", order_id = 7).in_scope(|| {
+                "x".parse::<u32>()
+                    .wrap_err("Invalid order quantity")
+                    .unwrap_err()
+            })
+        });
+
+        let error = ErrorReport::from_report(&report);
+
+        assert_eq!(error.kind, "core::num::error::ParseIntError");
+        assert_eq!(
+            error.chain,
+            vec![
+                "Invalid order quantity".to_string(),
+                "invalid digit found in string".to_string()
+            ]
+        );
+        assert_eq!(error.span_trace[0].name, "load_order");
+        assert!(error.span_trace[0].fields.contains("order_id"));
+        assert!(!error.report.unwrap().contains('\u{1b}'));
+    }
+
+    #[test]
+    fn error_report_from_std_error() {
+        let error = std::io::Error::other("disk full");
+        let error = ErrorReport::from_error(&error);
+
+        assert_eq!(error.kind, "std::io::error::Error");
+        assert_eq!(error.chain, vec!["disk full".to_string()]);
+        assert!(error.backtrace.is_none());
+    }
+
+    #[test]
+    fn error_kind_of_unknown_errors() {
+        #[derive(Debug)]
+        struct OrderNotFound(u64);
+
+        impl std::fmt::Display for OrderNotFound {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "order {} not found", self.0)
+            }
+        }
+
+        impl std::error::Error for OrderNotFound {}
+
+        assert_eq!(error_kind(&OrderNotFound(7)), "OrderNotFound");
+    }
+
+    #[test]
+    fn strip_ansi_sequences() {
+        assert_eq!(strip_ansi("\u{1b}[31mError\u{1b}[0m: x"), "Error: x");
+    }
+}
@@ -0,0 +1,677 @@
+use std::{
+    any::{Any, TypeId},
+    collections::BTreeMap,
+    fmt::{Debug, Display, Formatter},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{ArgMatches, Command, FromArgMatches};
+use eyre::WrapErr;
+use futures_util::future::{join_all, BoxFuture};
+
+use crate::health_status::HealthStatusReport;
+use crate::{async_trait, throw, Args, Result};
+
+#[cfg(feature = "postgres")]
+use crate::Postgres;
+
+#[cfg(feature = "redis")]
+use crate::Redis;
+
+#[cfg(feature = "streaming")]
+use crate::KafkaClient;
+
+// -----------------------------------------------------------------------------
+// Feature
+// -----------------------------------------------------------------------------
+
+/// Integration initialized along with the service, like a database pool or a client of another
+/// service, and retrieved by type from [`Features`].
+///
+/// Its configuration is parsed with the configuration of the service, so its arguments must not
+/// clash with the arguments of other features.
+#[async_trait]
+pub trait Feature: Send + Sync + Sized + 'static {
+    /// Configuration of the feature, read from every configuration source.
+    type Config: Args + Send + Sync;
+
+    /// Name of the feature in health reports and logs, like `postgres`.
+    const NAME: &'static str;
+
+    /// Title of the section of the feature in the configuration reference, like `PostgreSQL`.
+    const TITLE: &'static str = Self::NAME;
+
+    /// Cargo feature of balthazar registering the feature, documented in the configuration
+    /// reference.
+    const CARGO_FEATURE: Option<&'static str> = None;
+
+    /// Features initialized before this one, available from the [`Features`] given to `init`.
+    fn dependencies() -> Vec<FeatureId> {
+        Vec::new()
+    }
+
+    async fn init(service_name: &str, config: &Self::Config, features: &Features) -> Result<Self>;
+
+    /// Checks the feature is usable, reported by the health route and `check-config`.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Releases the resources of the feature, after the features depending on it.
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Identifies a feature by type, keeping its name for error messages.
+#[derive(Debug, Clone, Copy)]
+pub struct FeatureId {
+    type_id: TypeId,
+    name: &'static str,
+}
+
+impl FeatureId {
+    pub fn of<F: Feature>() -> Self {
+        Self {
+            type_id: TypeId::of::<F>(),
+            name: F::NAME,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl PartialEq for FeatureId {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_id == other.type_id
+    }
+}
+
+impl Eq for FeatureId {}
+
+/// Object safe part of [`Feature`], used once the feature is initialized.
+#[async_trait]
+trait AnyFeature: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    async fn health_check(&self) -> Result<()>;
+
+    async fn shutdown(&self) -> Result<()>;
+}
+
+#[async_trait]
+impl<F: Feature> AnyFeature for F {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        Feature::health_check(self).await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Feature::shutdown(self).await
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Registry
+// -----------------------------------------------------------------------------
+type InitFeature =
+    for<'a> fn(&'a str, &'a ArgMatches, &'a Features) -> BoxFuture<'a, Result<Arc<dyn AnyFeature>>>;
+
+struct Registration {
+    id: FeatureId,
+    dependencies: Vec<FeatureId>,
+    title: &'static str,
+    cargo_feature: Option<&'static str>,
+    augment_args: fn(Command) -> Command,
+    init: InitFeature,
+}
+
+/// Features initialized by `Config::init_with_features`.
+///
+/// Features without dependencies on each other are initialized concurrently, after the features
+/// they depend on.
+pub struct FeatureRegistry {
+    registrations: Vec<Registration>,
+}
+
+impl FeatureRegistry {
+    /// Registry of the features enabled by cargo features, like `Postgres` with `postgres`.
+    pub fn new() -> Self {
+        #[allow(unused_mut)]
+        let mut registry = Self::empty();
+
+        #[cfg(feature = "postgres")]
+        {
+            registry = registry.register::<Postgres>();
+        }
+
+        #[cfg(feature = "redis")]
+        {
+            registry = registry.register::<Redis>();
+        }
+
+        #[cfg(feature = "streaming")]
+        {
+            registry = registry.register::<KafkaClient>();
+        }
+
+        registry
+    }
+
+    /// Registry without any feature.
+    pub fn empty() -> Self {
+        Self {
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Adds a feature, ignored when it is already registered.
+    pub fn register<F: Feature>(mut self) -> Self {
+        let id = FeatureId::of::<F>();
+        if !self.registrations.iter().any(|r| r.id == id) {
+            self.registrations.push(Registration {
+                id,
+                dependencies: F::dependencies(),
+                title: F::TITLE,
+                cargo_feature: F::CARGO_FEATURE,
+                augment_args: F::Config::augment_args,
+                init: init_feature::<F>,
+            });
+        }
+        self
+    }
+
+    /// Adds the arguments of every feature to the command.
+    pub(crate) fn augment_args(&self, command: Command) -> Command {
+        self.registrations
+            .iter()
+            .fold(command, |command, r| (r.augment_args)(command))
+    }
+
+    /// Title, cargo feature and arguments of every feature, in registration order.
+    pub(crate) fn sections(
+        &self,
+    ) -> impl Iterator<Item = (&'static str, Option<&'static str>, Command)> + '_ {
+        self.registrations.iter().map(|r| {
+            let command = (r.augment_args)(Command::new(r.id.name));
+            (r.title, r.cargo_feature, command)
+        })
+    }
+
+    /// Initializes every feature, failing with the first error once the features already
+    /// initialized are shut down.
+    pub(crate) async fn init(&self, service_name: &str, matches: &ArgMatches) -> Result<Features> {
+        let (features, results) = self.start(service_name, matches, None, true).await?;
+        if let Some(error) = results.into_iter().find_map(|(_, result)| result.err()) {
+            features.shutdown().await;
+            return Err(error);
+        }
+        Ok(features)
+    }
+
+    /// Initializes, checks the health of and shuts down every feature, returning the result of
+    /// each one.
+    ///
+    /// Features depending on a failed feature are not initialized, but reported as
+    /// [`SkippedFeature`].
+    ///
+    /// Both the initialization and the health check of each feature must complete in time.
+    pub(crate) async fn check(
+        &self,
+        service_name: &str,
+        matches: &ArgMatches,
+        timeout: Duration,
+    ) -> Result<Vec<(&'static str, Result<()>)>> {
+        let (features, results) = self
+            .start(service_name, matches, Some(timeout), false)
+            .await?;
+        let mut checks = Vec::new();
+        for (id, result) in results {
+            let result = match features.find(id) {
+                Some(feature) => within(Some(timeout), feature.health_check()).await,
+                None => result,
+            };
+            checks.push((id.name, result));
+        }
+        features.shutdown().await;
+        Ok(checks)
+    }
+
+    /// Initializes the features of each level concurrently, stopping after the first level where
+    /// a feature failed if `stop_on_failure`, or else skipping the features depending on it.
+    async fn start(
+        &self,
+        service_name: &str,
+        matches: &ArgMatches,
+        timeout: Option<Duration>,
+        stop_on_failure: bool,
+    ) -> Result<(Features, Vec<(FeatureId, Result<()>)>)> {
+        let mut features = Features::default();
+        let mut results = Vec::new();
+        for level in self.levels()? {
+            let (level, skipped): (Vec<_>, Vec<_>) = level
+                .into_iter()
+                .partition(|r| r.dependencies.iter().all(|d| features.find(*d).is_some()));
+            for r in skipped {
+                let dependency = r
+                    .dependencies
+                    .iter()
+                    .find(|d| features.find(**d).is_none())
+                    .map_or("", |d| d.name);
+                results.push((r.id, Err(SkippedFeature { dependency }.into())));
+            }
+
+            let inits = level
+                .iter()
+                .map(|r| within(timeout, (r.init)(service_name, matches, &features)));
+            let initialized = join_all(inits).await;
+
+            let failed = initialized.iter().any(|result| result.is_err());
+            for (r, result) in level.into_iter().zip(initialized) {
+                let result = match result {
+                    Ok(feature) => {
+                        features.features.push((r.id, feature));
+                        Ok(())
+                    }
+                    Err(e) => {
+                        Err(e.wrap_err(format!("Failed to initialize feature `{}`", r.id.name)))
+                    }
+                };
+                results.push((r.id, result));
+            }
+            if failed && stop_on_failure {
+                break;
+            }
+        }
+        Ok((features, results))
+    }
+
+    /// Groups features so each one comes after the features it depends on.
+    fn levels(&self) -> Result<Vec<Vec<&Registration>>> {
+        for r in &self.registrations {
+            if let Some(missing) = r
+                .dependencies
+                .iter()
+                .find(|d| !self.registrations.iter().any(|other| other.id == **d))
+            {
+                return Err(throw!(
+                    "Feature `{}` depends on `{}`, which is not registered",
+                    r.id.name,
+                    missing.name
+                ));
+            }
+        }
+
+        let mut pending: Vec<&Registration> = self.registrations.iter().collect();
+        let mut done: Vec<FeatureId> = Vec::new();
+        let mut levels = Vec::new();
+        while !pending.is_empty() {
+            let (ready, blocked): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|r| r.dependencies.iter().all(|d| done.contains(d)));
+            if ready.is_empty() {
+                let names: Vec<_> = blocked.iter().map(|r| r.id.name).collect();
+                return Err(throw!(
+                    "Dependency cycle between features: {}",
+                    names.join(", ")
+                ));
+            }
+            done.extend(ready.iter().map(|r| r.id));
+            levels.push(ready);
+            pending = blocked;
+        }
+        Ok(levels)
+    }
+}
+
+impl Default for FeatureRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for FeatureRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.registrations.iter().map(|r| r.id.name))
+            .finish()
+    }
+}
+
+/// Result of a feature not initialized by a check, because one of its dependencies failed.
+#[derive(Debug)]
+pub(crate) struct SkippedFeature {
+    dependency: &'static str,
+}
+
+impl Display for SkippedFeature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dependency `{}` failed", self.dependency)
+    }
+}
+
+impl std::error::Error for SkippedFeature {}
+
+async fn within<R>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<R>>,
+) -> Result<R> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .wrap_err("Timed out")?,
+        None => future.await,
+    }
+}
+
+fn init_feature<'a, F: Feature>(
+    service_name: &'a str,
+    matches: &'a ArgMatches,
+    features: &'a Features,
+) -> BoxFuture<'a, Result<Arc<dyn AnyFeature>>> {
+    Box::pin(async move {
+        let config = F::Config::from_arg_matches(matches)
+            .wrap_err_with(|| format!("Invalid configuration of feature `{}`", F::NAME))?;
+        let feature = F::init(service_name, &config, features).await?;
+        Ok(Arc::new(feature) as Arc<dyn AnyFeature>)
+    })
+}
+
+// -----------------------------------------------------------------------------
+// Features
+// -----------------------------------------------------------------------------
+
+/// Initialized features, in initialization order.
+#[derive(Clone, Default)]
+pub struct Features {
+    features: Vec<(FeatureId, Arc<dyn AnyFeature>)>,
+}
+
+impl Features {
+    /// Feature of the given type, if it is registered.
+    pub fn get<F: Feature>(&self) -> Option<&F> {
+        self.find(FeatureId::of::<F>())
+            .and_then(|feature| feature.as_any().downcast_ref())
+    }
+
+    /// Feature of the given type, failing when it is not registered.
+    pub fn require<F: Feature>(&self) -> Result<&F> {
+        self.get()
+            .ok_or_else(|| throw!("Feature `{}` is not registered", F::NAME))
+    }
+
+    fn find(&self, id: FeatureId) -> Option<&Arc<dyn AnyFeature>> {
+        self.features
+            .iter()
+            .find(|(feature_id, _)| *feature_id == id)
+            .map(|(_, feature)| feature)
+    }
+
+    /// Checks the health of every feature concurrently.
+    pub(crate) async fn health_checks(
+        &self,
+        timeout_ms: u64,
+        degrade_ms: u64,
+    ) -> BTreeMap<&'static str, HealthStatusReport> {
+        let checks = self.features.iter().map(|(id, feature)| async move {
+            let report = HealthStatusReport::check_with_timeout_and_degrade(
+                feature.health_check(),
+                timeout_ms,
+                degrade_ms,
+            )
+            .await;
+            (id.name, report)
+        });
+        join_all(checks).await.into_iter().collect()
+    }
+
+    /// Shuts down every feature, in reverse initialization order.
+    pub async fn shutdown(&self) {
+        for (id, feature) in self.features.iter().rev() {
+            if let Err(e) = feature.shutdown().await {
+                tracing::error!(reason = ?e, feature = id.name, "failed to shut down feature");
+            }
+        }
+    }
+}
+
+impl Debug for Features {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.features.iter().map(|(id, _)| id.name))
+            .finish()
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use clap::Command;
+    use once_cell::sync::Lazy;
+
+    use crate::error_reporting::install_test_hooks;
+    use crate::{async_trait, throw, Args, Result};
+
+    use super::{Feature, FeatureId, FeatureRegistry, Features, SkippedFeature};
+
+    /// Features shut down, in order.
+    static SHUTDOWNS: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    /// Serializes the tests recording shutdowns.
+    static SHUTDOWN_TESTS: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+
+    #[derive(Debug, Args)]
+    struct StoreConfig {
+        #[clap(long = "feature-store-size", default_value = "16")]
+        size: usize,
+    }
+
+    struct Store {
+        size: usize,
+    }
+
+    #[async_trait]
+    impl Feature for Store {
+        type Config = StoreConfig;
+        const NAME: &'static str = "store";
+
+        async fn init(_: &str, config: &StoreConfig, _: &Features) -> Result<Self> {
+            Ok(Self { size: config.size })
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            SHUTDOWNS.lock().unwrap().push("store");
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Args)]
+    struct CacheConfig {
+        #[clap(long = "feature-cache-ratio", default_value = "2")]
+        ratio: usize,
+    }
+
+    struct Cache {
+        capacity: usize,
+    }
+
+    #[async_trait]
+    impl Feature for Cache {
+        type Config = CacheConfig;
+        const NAME: &'static str = "cache";
+
+        fn dependencies() -> Vec<FeatureId> {
+            vec![FeatureId::of::<Store>()]
+        }
+
+        async fn init(_: &str, config: &CacheConfig, features: &Features) -> Result<Self> {
+            let store = features.require::<Store>()?;
+            Ok(Self {
+                capacity: store.size / config.ratio,
+            })
+        }
+
+        async fn health_check(&self) -> Result<()> {
+            Err(throw!("cache is cold"))
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            SHUTDOWNS.lock().unwrap().push("cache");
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Args)]
+    struct FailingConfig {}
+
+    struct Failing;
+
+    #[async_trait]
+    impl Feature for Failing {
+        type Config = FailingConfig;
+        const NAME: &'static str = "failing";
+
+        async fn init(_: &str, _: &FailingConfig, _: &Features) -> Result<Self> {
+            Err(throw!("connection refused"))
+        }
+    }
+
+    #[derive(Debug, Args)]
+    struct ReplicaConfig {}
+
+    /// Depends on the failing feature, so it is never initialized.
+    struct Replica;
+
+    #[async_trait]
+    impl Feature for Replica {
+        type Config = ReplicaConfig;
+        const NAME: &'static str = "replica";
+
+        fn dependencies() -> Vec<FeatureId> {
+            vec![FeatureId::of::<Failing>()]
+        }
+
+        async fn init(_: &str, _: &ReplicaConfig, _: &Features) -> Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    struct Broken;
+
+    #[async_trait]
+    impl Feature for Broken {
+        type Config = StoreConfig;
+        const NAME: &'static str = "broken";
+
+        fn dependencies() -> Vec<FeatureId> {
+            vec![FeatureId::of::<Broken>()]
+        }
+
+        async fn init(_: &str, _: &StoreConfig, _: &Features) -> Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[tokio::test]
+    async fn init_features_in_dependency_order() {
+        install_test_hooks();
+        let _serial = SHUTDOWN_TESTS.lock().await;
+        SHUTDOWNS.lock().unwrap().clear();
+        // registered before their dependency, and twice
+        let registry = FeatureRegistry::empty()
+            .register::<Cache>()
+            .register::<Store>()
+            .register::<Cache>();
+        let matches = registry
+            .augment_args(Command::new("test"))
+            .get_matches_from(["test", "--feature-store-size=64"]);
+
+        let features = registry.init("test", &matches).await.unwrap();
+        assert_eq!(features.get::<Store>().unwrap().size, 64);
+        assert_eq!(features.get::<Cache>().unwrap().capacity, 32);
+        assert!(features.get::<Broken>().is_none());
+
+        let checks = registry
+            .check("test", &matches, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(checks[0].1.is_ok());
+        assert_eq!(checks[1].0, "cache");
+        assert!(checks[1].1.is_err());
+        // features started by the check are shut down after it
+        assert_eq!(*SHUTDOWNS.lock().unwrap(), vec!["cache", "store"]);
+        SHUTDOWNS.lock().unwrap().clear();
+
+        features.shutdown().await;
+        assert_eq!(*SHUTDOWNS.lock().unwrap(), vec!["cache", "store"]);
+    }
+
+    #[tokio::test]
+    async fn shut_down_features_when_a_level_fails() {
+        install_test_hooks();
+        let _serial = SHUTDOWN_TESTS.lock().await;
+        SHUTDOWNS.lock().unwrap().clear();
+        // the cache depends on the store, so it comes in the level after the failing feature
+        let registry = FeatureRegistry::empty()
+            .register::<Store>()
+            .register::<Failing>()
+            .register::<Cache>()
+            .register::<Replica>();
+        let matches = registry
+            .augment_args(Command::new("test"))
+            .get_matches_from(["test"]);
+
+        let error = registry.init("test", &matches).await.unwrap_err();
+        assert_eq!(error.to_string(), "Failed to initialize feature `failing`");
+        assert_eq!(*SHUTDOWNS.lock().unwrap(), vec!["store"]);
+        SHUTDOWNS.lock().unwrap().clear();
+
+        let checks = registry
+            .check("test", &matches, Duration::from_secs(1))
+            .await
+            .unwrap();
+        // only the dependents of the failing feature are skipped
+        let names: Vec<_> = checks.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["store", "failing", "replica", "cache"]);
+        assert!(checks[1].1.is_err());
+        let skipped = checks[2].1.as_ref().unwrap_err();
+        assert!(skipped.is::<SkippedFeature>());
+        assert_eq!(skipped.to_string(), "dependency `failing` failed");
+        assert_eq!(
+            checks[3].1.as_ref().unwrap_err().to_string(),
+            "cache is cold"
+        );
+        assert_eq!(*SHUTDOWNS.lock().unwrap(), vec!["cache", "store"]);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_dependencies() {
+        install_test_hooks();
+        let matches = Command::new("test").get_matches_from(["test"]);
+
+        let missing = FeatureRegistry::empty().register::<Cache>();
+        let error = missing.init("test", &matches).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Feature `cache` depends on `store`, which is not registered"
+        );
+
+        let cycle = FeatureRegistry::empty().register::<Broken>();
+        let error = cycle.init("test", &matches).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Dependency cycle between features: broken"
+        );
+    }
+}
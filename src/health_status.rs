@@ -14,7 +14,7 @@ pub struct HealthStatusReport {
     pub duration: Duration,
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub enum HealthStatus {
     Healthy,
     Degraded,
@@ -0,0 +1,599 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use axum::{
+    extract::DefaultBodyLimit,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use eyre::WrapErr;
+use futures_util::FutureExt;
+use metrics::{describe_gauge, gauge, Label};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tower_http::{
+    request_id::{PropagateRequestIdLayer, SetRequestIdLayer},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+
+use crate::health_status::{HealthStatus, HealthStatusReport};
+use crate::resource::{metric_name, ResourceConfig};
+use crate::trace::{
+    request_id_header, LogFilter, MakeSpanWithRedaction, RecordFailure, RecordResponse,
+    UuidMakeRequestId,
+};
+use crate::{Environment, Features, Parser, Result};
+
+const HEALTH_ROUTE: &str = "/health";
+const METRICS_ROUTE: &str = "/metrics";
+const LOG_FILTER_ROUTE: &str = "/admin/log-filter";
+
+const BUILD_INFO_METRIC_SUFFIX: &str = "build_info";
+
+const HEALTH_CHECK_TIMEOUT_MS: u64 = 1000;
+const HEALTH_CHECK_DEGRADE_MS: u64 = 250;
+
+static METRICS_HANDLE: OnceCell<PrometheusHandle> = OnceCell::new();
+
+// -----------------------------------------------------------------------------
+// Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Parser)]
+pub struct HttpServerConfig {
+    /// Address the HTTP server started by `Environment::serve` binds to.
+    #[clap(
+        long = "http-bind-address",
+        env = "HTTP_BIND_ADDRESS",
+        default_value = "0.0.0.0"
+    )]
+    pub http_bind_address: IpAddr,
+
+    /// Port the HTTP server started by `Environment::serve` listens on.
+    #[clap(long = "http-port", env = "HTTP_PORT", default_value = "8080")]
+    pub http_port: u16,
+
+    /// Maximum time in milliseconds a request can take before being answered with `408 Request Timeout`.
+    #[clap(
+        long = "http-request-timeout-ms",
+        env = "HTTP_REQUEST_TIMEOUT_MS",
+        default_value = "30000"
+    )]
+    pub http_request_timeout_ms: u64,
+
+    /// Maximum size in bytes of request bodies read by extractors.
+    #[clap(
+        long = "http-body-limit",
+        env = "HTTP_BODY_LIMIT",
+        default_value = "2097152"
+    )]
+    pub http_body_limit: usize,
+
+    /// Comma-separated headers recorded in request spans. All headers are recorded when empty.
+    #[clap(
+        long = "http-trace-allowed-headers",
+        env = "HTTP_TRACE_ALLOWED_HEADERS",
+        value_delimiter = ','
+    )]
+    pub http_trace_allowed_headers: Option<Vec<String>>,
+
+    /// Comma-separated headers never recorded in request spans.
+    #[clap(
+        long = "http-trace-denied-headers",
+        env = "HTTP_TRACE_DENIED_HEADERS",
+        value_delimiter = ','
+    )]
+    pub http_trace_denied_headers: Vec<String>,
+
+    /// Comma-separated headers masked in request spans, in addition to known credential headers.
+    #[clap(
+        long = "http-trace-redacted-headers",
+        env = "HTTP_TRACE_REDACTED_HEADERS",
+        value_delimiter = ','
+    )]
+    pub http_trace_redacted_headers: Vec<String>,
+
+    /// Comma-separated query string parameters masked in request spans, in addition to known
+    /// credential parameters.
+    #[clap(
+        long = "http-trace-redacted-query-params",
+        env = "HTTP_TRACE_REDACTED_QUERY_PARAMS",
+        value_delimiter = ','
+    )]
+    pub http_trace_redacted_query_params: Vec<String>,
+
+    /// Port of a second HTTP server serving admin routes, like the log filter route. Admin routes
+    /// are not served when not set.
+    #[clap(long = "http-admin-port", env = "HTTP_ADMIN_PORT")]
+    pub http_admin_port: Option<u16>,
+
+    /// Address the admin HTTP server binds to. Admin routes are not authenticated, so they should
+    /// only be reachable from trusted networks.
+    #[clap(
+        long = "http-admin-bind-address",
+        env = "HTTP_ADMIN_BIND_ADDRESS",
+        default_value = "127.0.0.1"
+    )]
+    pub http_admin_bind_address: IpAddr,
+
+    /// Time in seconds after which log filter directives changed through the admin route revert to
+    /// `TRACING_LOG_LEVEL`, when the request does not specify one. Zero keeps them until restart.
+    #[clap(
+        long = "http-log-filter-ttl-secs",
+        env = "HTTP_LOG_FILTER_TTL_SECS",
+        default_value = "600"
+    )]
+    pub http_log_filter_ttl_secs: u64,
+}
+
+impl HttpServerConfig {
+    pub fn socket_address(&self) -> SocketAddr {
+        SocketAddr::new(self.http_bind_address, self.http_port)
+    }
+
+    /// Address of the admin HTTP server, when enabled.
+    pub fn admin_socket_address(&self) -> Option<SocketAddr> {
+        self.http_admin_port
+            .map(|port| SocketAddr::new(self.http_admin_bind_address, port))
+    }
+
+    /// Creates the request span maker with the configured header and query string redaction.
+    pub fn make_span(&self) -> MakeSpanWithRedaction {
+        let mut make_span = MakeSpanWithRedaction::default()
+            .deny_headers(&self.http_trace_denied_headers)
+            .redact_headers(&self.http_trace_redacted_headers)
+            .redact_query_params(&self.http_trace_redacted_query_params);
+        if let Some(allowed_headers) = &self.http_trace_allowed_headers {
+            make_span = make_span.allow_headers(allowed_headers);
+        }
+        make_span
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Metrics
+// -----------------------------------------------------------------------------
+/// Installs a Prometheus recorder as the global `metrics` recorder so it can be exposed by the
+/// metrics route, labeling every metric with the resource attributes that are the same for every
+/// pod and deploy. Build attributes are published once, by the `<service>_build_info` gauge.
+///
+/// If the application already installed its own recorder, this is a no-op and the metrics route
+/// will not be mounted.
+pub(crate) fn init_metrics(service_name: &str, resource: &ResourceConfig) {
+    if METRICS_HANDLE.get().is_some() {
+        return;
+    }
+    let builder = resource
+        .metric_labels()
+        .into_iter()
+        .fold(PrometheusBuilder::new(), |builder, (key, value)| {
+            builder.add_global_label(key, value)
+        });
+    match builder.install_recorder() {
+        Ok(handle) => {
+            let _ = METRICS_HANDLE.set(handle);
+        }
+        Err(e) => {
+            tracing::debug!(reason = %e, "metrics recorder not installed");
+            return;
+        }
+    }
+
+    let build_labels: Vec<Label> = resource
+        .build_info_labels()
+        .into_iter()
+        .map(|(key, value)| Label::new(key, value))
+        .collect();
+    if !build_labels.is_empty() {
+        let metric_name = metric_name(service_name, BUILD_INFO_METRIC_SUFFIX);
+        describe_gauge!(
+            metric_name.clone(),
+            "Always 1, labeled with the version, commit and branch of the running build."
+        );
+        gauge!(metric_name, 1.0, build_labels);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Health
+// -----------------------------------------------------------------------------
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: String,
+    checks: BTreeMap<&'static str, HealthStatusReport>,
+}
+
+async fn health(features: &Features) -> (StatusCode, Json<HealthResponse>) {
+    let checks = features
+        .health_checks(HEALTH_CHECK_TIMEOUT_MS, HEALTH_CHECK_DEGRADE_MS)
+        .await;
+
+    let status = overall_status(checks.values().map(|report| &report.status));
+    let status_code = match status {
+        HealthStatus::Offline { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+
+    (
+        status_code,
+        Json(HealthResponse {
+            status: status.to_string(),
+            checks,
+        }),
+    )
+}
+
+/// Worst status among all checks.
+fn overall_status<'a>(statuses: impl Iterator<Item = &'a HealthStatus>) -> HealthStatus {
+    let mut overall = HealthStatus::Healthy;
+    for status in statuses {
+        match status {
+            HealthStatus::Offline { error } => {
+                return HealthStatus::Offline {
+                    error: error.clone(),
+                }
+            }
+            HealthStatus::Degraded => overall = HealthStatus::Degraded,
+            HealthStatus::Healthy => {}
+        }
+    }
+    overall
+}
+
+// -----------------------------------------------------------------------------
+// Log Filter
+// -----------------------------------------------------------------------------
+#[derive(Debug, Deserialize)]
+struct LogFilterRequest {
+    directives: String,
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LogFilterResponse {
+    directives: String,
+    default_directives: String,
+}
+
+fn log_filter_response(log_filter: &LogFilter) -> Response {
+    match log_filter.directives() {
+        Ok(directives) => Json(LogFilterResponse {
+            directives,
+            default_directives: log_filter.default_directives().to_string(),
+        })
+        .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response(),
+    }
+}
+
+fn set_log_filter(
+    log_filter: &LogFilter,
+    request: LogFilterRequest,
+    default_ttl_secs: u64,
+) -> Response {
+    // a TTL of zero keeps the directives until they are changed again or the service restarts
+    let ttl = match request.ttl_secs.unwrap_or(default_ttl_secs) {
+        0 => None,
+        ttl_secs => Some(Duration::from_secs(ttl_secs)),
+    };
+    match log_filter.set(&request.directives, ttl) {
+        Ok(()) => log_filter_response(log_filter),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("{:#}", e)).into_response(),
+    }
+}
+
+fn reset_log_filter(log_filter: &LogFilter) -> Response {
+    match log_filter.reset() {
+        Ok(()) => log_filter_response(log_filter),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response(),
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Server
+// -----------------------------------------------------------------------------
+impl<T: std::fmt::Debug + crate::Args> Environment<T> {
+    /// Serves the router with the configured request tracing, request ID propagation and request
+    /// timeout, along with health and metrics routes, until a shutdown signal is received, then
+    /// shuts down every feature.
+    ///
+    /// Admin routes are served by a second server when `HTTP_ADMIN_PORT` is set.
+    pub async fn serve(&self, router: Router) -> Result<()> {
+        let config = &self.config.environment.http;
+        let app = build_router(router, config, self.features.clone());
+        let shutdown = shutdown_signal().shared();
+
+        let address = config.socket_address();
+        tracing::info!(%address, "starting http server");
+        let server = axum::Server::try_bind(&address)
+            .wrap_err_with(|| format!("Failed to bind http server to {}", address))?
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(shutdown.clone());
+
+        let admin_server = match config.admin_socket_address() {
+            Some(address) => {
+                let admin = admin_router(self.tracing.log_filter().cloned(), config);
+                tracing::info!(%address, "starting admin http server");
+                let server = axum::Server::try_bind(&address)
+                    .wrap_err_with(|| format!("Failed to bind admin http server to {}", address))?
+                    .serve(admin.into_make_service())
+                    .with_graceful_shutdown(shutdown);
+                Some(server)
+            }
+            None => None,
+        };
+        let admin_server = async {
+            match admin_server {
+                Some(server) => server.await.wrap_err("Failed to run admin http server"),
+                None => Ok(()),
+            }
+        };
+
+        tokio::try_join!(
+            async { server.await.wrap_err("Failed to run http server") },
+            admin_server
+        )?;
+
+        tracing::info!("stopped http server");
+        self.shutdown().await;
+        Ok(())
+    }
+}
+
+fn build_router(router: Router, config: &HttpServerConfig, features: Features) -> Router {
+    // layers are applied only to routes added before them, so health and metrics routes are
+    // neither traced nor timed out
+    let router = router
+        .layer(DefaultBodyLimit::max(config.http_body_limit))
+        .layer(TimeoutLayer::new(Duration::from_millis(
+            config.http_request_timeout_ms,
+        )))
+        .layer(PropagateRequestIdLayer::new(request_id_header().clone()))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(config.make_span())
+                .on_response(RecordResponse)
+                .on_failure(RecordFailure),
+        )
+        .layer(SetRequestIdLayer::new(
+            request_id_header().clone(),
+            UuidMakeRequestId::default(),
+        ))
+        .route(
+            HEALTH_ROUTE,
+            get(move || {
+                let features = features.clone();
+                async move { health(&features).await }
+            }),
+        );
+
+    match METRICS_HANDLE.get() {
+        Some(handle) => {
+            let handle = handle.clone();
+            router.route(METRICS_ROUTE, get(move || async move { handle.render() }))
+        }
+        None => router,
+    }
+}
+
+/// Routes of the admin server. The log filter route is only mounted when events are formatted.
+fn admin_router(log_filter: Option<LogFilter>, config: &HttpServerConfig) -> Router {
+    let router = Router::new();
+    match log_filter {
+        Some(log_filter) => {
+            let ttl_secs = config.http_log_filter_ttl_secs;
+            let put_log_filter = log_filter.clone();
+            let delete_log_filter = log_filter.clone();
+            router.route(
+                LOG_FILTER_ROUTE,
+                get(move || async move { log_filter_response(&log_filter) })
+                    .put(move |Json(request): Json<LogFilterRequest>| async move {
+                        set_log_filter(&put_log_filter, request, ttl_secs)
+                    })
+                    .delete(move || async move { reset_log_filter(&delete_log_filter) }),
+            )
+        }
+        None => router,
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(reason = ?e, "failed to listen to ctrl-c signal");
+            futures_util::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(reason = ?e, "failed to listen to terminate signal");
+                futures_util::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = futures_util::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("received shutdown signal");
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, HttpBody as _};
+    use http::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn config() -> HttpServerConfig {
+        HttpServerConfig::parse_from(["test"])
+    }
+
+    #[tokio::test]
+    async fn serve_generates_request_id() {
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let app = build_router(router, &config(), Features::default());
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("x-request-id"));
+    }
+
+    #[tokio::test]
+    async fn serve_propagates_request_id() {
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let app = build_router(router, &config(), Features::default());
+
+        let response = app
+            .oneshot(
+                Request::get("/")
+                    .header("x-request-id", "abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-request-id"], "abc");
+    }
+
+    #[tokio::test]
+    async fn serve_mounts_health_route() {
+        let app = build_router(Router::new(), &config(), Features::default());
+
+        let response = app
+            .oneshot(Request::get(HEALTH_ROUTE).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn overall_status_is_worst_status() {
+        let offline = HealthStatus::Offline {
+            error: "down".to_string(),
+        };
+        assert_eq!(overall_status([].iter()), HealthStatus::Healthy);
+        assert_eq!(
+            overall_status([HealthStatus::Healthy, HealthStatus::Degraded].iter()),
+            HealthStatus::Degraded
+        );
+        assert_eq!(
+            overall_status([HealthStatus::Degraded, offline.clone()].iter()),
+            offline
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_log_filter_only_on_admin_router() {
+        let get_log_filter = || Request::get(LOG_FILTER_ROUTE).body(Body::empty()).unwrap();
+
+        let app = build_router(Router::new(), &config(), Features::default());
+        let response = app.oneshot(get_log_filter()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // events are not formatted, so there is no log filter
+        let admin = admin_router(None, &config());
+        let response = admin.oneshot(get_log_filter()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(config().admin_socket_address(), None);
+
+        let config = HttpServerConfig::parse_from(["test", "--http-admin-port=9090"]);
+        assert_eq!(
+            config.admin_socket_address(),
+            Some("127.0.0.1:9090".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_changes_log_filter() {
+        let (_layer, log_filter) = LogFilter::new("info").unwrap();
+        let app = admin_router(Some(log_filter.clone()), &config());
+        let put = |body: &'static str| {
+            Request::put(LOG_FILTER_ROUTE)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(put(r#"{"directives": "debug"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(log_filter.directives().unwrap(), "debug");
+
+        let response = app
+            .clone()
+            .oneshot(put(r#"{"directives": "info,=["}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(log_filter.directives().unwrap(), "debug");
+
+        let response = app
+            .oneshot(Request::get(LOG_FILTER_ROUTE).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().data().await.unwrap().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["directives"], "debug");
+        assert_eq!(body["default_directives"], "info");
+    }
+
+    #[tokio::test]
+    async fn serve_keeps_and_resets_log_filter() {
+        let (_layer, log_filter) = LogFilter::new("info").unwrap();
+        let config = HttpServerConfig::parse_from(["test", "--http-log-filter-ttl-secs=1"]);
+        let app = admin_router(Some(log_filter.clone()), &config);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::put(LOG_FILTER_ROUTE)
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"directives": "debug", "ttl_secs": 0}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // outlives the default TTL
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(log_filter.directives().unwrap(), "debug");
+
+        let response = app
+            .oneshot(
+                Request::delete(LOG_FILTER_ROUTE)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(log_filter.directives().unwrap(), "info");
+    }
+}
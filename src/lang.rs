@@ -4,13 +4,24 @@
 ///
 /// Access to the original value can be obtained by derefing the SensitiveString to a String reference.
 pub mod sensitive {
+    use once_cell::sync::Lazy;
     use serde::{Deserialize, Serialize};
 
+    use super::secrets::resolve_reference;
+
+    use std::borrow::Cow;
     use std::fmt::{Debug, Display, Formatter};
     use std::ops::Deref;
     use std::str::FromStr;
+    use std::sync::RwLock;
+
+    pub(crate) const MASK: &str = "******";
+
+    /// Registered values shorter than this are not scrubbed, because they would mask unrelated text.
+    const MIN_SCRUBBED_LEN: usize = 4;
 
-    const MASK: &str = "******";
+    /// Values parsed as `Sensitive`, scrubbed from error reports.
+    static SECRETS: Lazy<RwLock<Vec<String>>> = Lazy::new(RwLock::default);
 
     #[derive(Clone, Serialize, Deserialize)]
     pub struct Sensitive<T>(pub T);
@@ -39,20 +50,75 @@ pub mod sensitive {
         }
     }
 
+    /// Values starting with `secret+` and a registered scheme, like
+    /// `secret+file:///run/secrets/db_url` or `secret+env:DB_URL`, are references resolved by the
+    /// [`SecretProvider`](super::secrets::SecretProvider) of the scheme before being parsed.
+    ///
+    /// Parsing fails with [`ParseSensitiveError`] rather than `T::Err`, because resolving a
+    /// reference can fail whatever `T` is: the error of `T` is kept in
+    /// [`ParseSensitiveError::Value`].
     impl<T> FromStr for Sensitive<T>
     where
         T: FromStr,
     {
-        type Err = T::Err;
+        type Err = ParseSensitiveError<T::Err>;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
-            match T::from_str(s) {
-                Ok(t) => Ok(Sensitive(t)),
-                Err(e) => Err(e),
+            let value = resolve_reference(s).map_err(ParseSensitiveError::Reference)?;
+            match T::from_str(&value) {
+                Ok(t) => {
+                    register_secret(&value);
+                    Ok(Sensitive(t))
+                }
+                Err(e) => Err(ParseSensitiveError::Value(e)),
+            }
+        }
+    }
+
+    /// Error parsing a [`Sensitive`] value.
+    #[derive(Debug)]
+    pub enum ParseSensitiveError<E> {
+        /// The secret reference could not be resolved.
+        Reference(String),
+
+        /// The value, or the resolved secret, is not valid.
+        Value(E),
+    }
+
+    impl<E: Display> Display for ParseSensitiveError<E> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ParseSensitiveError::Reference(message) => write!(f, "{}", message),
+                ParseSensitiveError::Value(e) => write!(f, "{}", e),
             }
         }
     }
 
+    impl<E: Debug + Display> std::error::Error for ParseSensitiveError<E> {}
+
+    /// Registers a secret value, so it is masked by [`scrub`] wherever it appears.
+    pub(crate) fn register_secret(secret: &str) {
+        if secret.len() < MIN_SCRUBBED_LEN {
+            return;
+        }
+        let mut secrets = SECRETS.write().unwrap_or_else(|e| e.into_inner());
+        if !secrets.iter().any(|existing| existing == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+
+    /// Masks every registered secret value found in the text.
+    pub(crate) fn scrub(text: &str) -> Cow<'_, str> {
+        let secrets = SECRETS.read().unwrap_or_else(|e| e.into_inner());
+        let mut text = Cow::Borrowed(text);
+        for secret in secrets.iter() {
+            if text.contains(secret.as_str()) {
+                text = Cow::Owned(text.replace(secret.as_str(), MASK));
+            }
+        }
+        text
+    }
+
     impl<T> Deref for Sensitive<T> {
         type Target = T;
 
@@ -106,6 +172,18 @@ pub mod sensitive {
             assert_eq!(*v.deref(), vec![1, 2, 3]);
         }
 
+        #[test]
+        fn test_sensitive_parsed_values_are_scrubbed() {
+            let _: Sensitive<String> = "postgres://user:s3cr3t-pa55@db".parse().unwrap();
+            let _: Sensitive<String> = "abc".parse().unwrap();
+
+            assert_eq!(
+                scrub("failed to connect to postgres://user:s3cr3t-pa55@db"),
+                format!("failed to connect to {}", MASK)
+            );
+            assert_eq!(scrub("abc"), "abc");
+        }
+
         #[test]
         fn test_sensitive_equality() {
             let sensitive = Sensitive::<String>("123456".to_string());
@@ -117,3 +195,227 @@ pub mod sensitive {
         }
     }
 }
+
+/// Secret references resolve values of `Sensitive` fields from another place than the command line
+/// or environment variable, like a file mounted by Kubernetes or a secret manager.
+///
+/// A reference is a value starting with `secret+`, the scheme of a registered [`SecretProvider`] and
+/// a colon, like `secret+env:DB_URL`. `file`, `env` and `literal` are always registered, the latter
+/// returning the rest of the value as is, to pass literal values starting with `secret+`.
+pub mod secrets {
+    use std::fs;
+    use std::sync::{Arc, RwLock};
+
+    use eyre::WrapErr;
+    use once_cell::sync::Lazy;
+
+    use crate::{throw, Result};
+
+    /// Prefix of secret references, so values are never taken for references by accident.
+    const REFERENCE_PREFIX: &str = "secret+";
+
+    /// Providers by scheme, the last registered first.
+    static PROVIDERS: Lazy<RwLock<Vec<Arc<dyn SecretProvider>>>> = Lazy::new(|| {
+        RwLock::new(vec![
+            Arc::new(FileSecretProvider) as Arc<dyn SecretProvider>,
+            Arc::new(EnvSecretProvider {
+                var: |name| std::env::var(name).ok(),
+            }),
+            Arc::new(LiteralSecretProvider),
+        ])
+    });
+
+    /// Resolves secret references of a scheme.
+    ///
+    /// Values are resolved while the configuration is parsed, so resolving must not depend on the
+    /// async runtime.
+    pub trait SecretProvider: Send + Sync {
+        /// Scheme of the references, without the colon, like `vault`.
+        fn scheme(&self) -> &str;
+
+        /// Returns the secret referenced by what follows the `secret+` prefix, the scheme and the
+        /// colon.
+        fn resolve(&self, reference: &str) -> Result<String>;
+    }
+
+    /// Registers a provider, replacing the one with the same scheme.
+    ///
+    /// Must be called before `Config::init` to resolve references in the configuration.
+    pub fn register_secret_provider<P: SecretProvider + 'static>(provider: P) {
+        let mut providers = PROVIDERS.write().unwrap_or_else(|e| e.into_inner());
+        providers.retain(|existing| existing.scheme() != provider.scheme());
+        providers.push(Arc::new(provider));
+    }
+
+    /// Resolves the value when it is a reference, otherwise returns it unchanged.
+    pub(crate) fn resolve_reference(value: &str) -> std::result::Result<String, String> {
+        let Some((scheme, reference)) = value
+            .strip_prefix(REFERENCE_PREFIX)
+            .and_then(|reference| reference.split_once(':'))
+        else {
+            return Ok(value.to_string());
+        };
+        let provider = {
+            let providers = PROVIDERS.read().unwrap_or_else(|e| e.into_inner());
+            providers
+                .iter()
+                .find(|provider| provider.scheme() == scheme)
+                .cloned()
+        };
+        match provider {
+            Some(provider) => provider
+                .resolve(reference)
+                .map_err(|e| format!("Failed to resolve secret reference `{}`: {:#}", value, e)),
+            None => Err(format!(
+                "Unknown scheme of secret reference `{}`, prefix literal values with `{}literal:`",
+                value, REFERENCE_PREFIX
+            )),
+        }
+    }
+
+    /// Reads `secret+file:///run/secrets/db_url`, without the trailing line break.
+    struct FileSecretProvider;
+
+    impl SecretProvider for FileSecretProvider {
+        fn scheme(&self) -> &str {
+            "file"
+        }
+
+        fn resolve(&self, reference: &str) -> Result<String> {
+            let path = reference.strip_prefix("//").unwrap_or(reference);
+            let secret = fs::read_to_string(path).wrap_err("Failed to read the file")?;
+            Ok(secret.trim_end_matches(['\n', '\r']).to_string())
+        }
+    }
+
+    /// Reads another environment variable, like `secret+env:DB_URL`.
+    struct EnvSecretProvider {
+        var: fn(&str) -> Option<String>,
+    }
+
+    impl SecretProvider for EnvSecretProvider {
+        fn scheme(&self) -> &str {
+            "env"
+        }
+
+        fn resolve(&self, reference: &str) -> Result<String> {
+            (self.var)(reference).ok_or_else(|| throw!("Environment variable not set"))
+        }
+    }
+
+    /// Returns the value as is, like `secret+literal:secret+env:value` for `secret+env:value`.
+    struct LiteralSecretProvider;
+
+    impl SecretProvider for LiteralSecretProvider {
+        fn scheme(&self) -> &str {
+            "literal"
+        }
+
+        fn resolve(&self, reference: &str) -> Result<String> {
+            Ok(reference.to_string())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+
+        use crate::config_source::TempFile;
+        use crate::error_reporting::install_test_hooks;
+        use crate::lang::sensitive::{scrub, MASK};
+        use crate::{throw, Result, Sensitive};
+
+        use super::{register_secret_provider, EnvSecretProvider, SecretProvider};
+
+        /// Stand-in of a secret manager reading secrets through HTTP.
+        struct HttpSecretProvider {
+            address: String,
+        }
+
+        impl SecretProvider for HttpSecretProvider {
+            fn scheme(&self) -> &str {
+                "http-test"
+            }
+
+            fn resolve(&self, reference: &str) -> Result<String> {
+                let mut stream = TcpStream::connect(&self.address)?;
+                let request = format!("GET /v1/{} HTTP/1.0\r\n\r\n", reference);
+                stream.write_all(request.as_bytes())?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response)?;
+                let (head, body) = response
+                    .split_once("\r\n\r\n")
+                    .ok_or_else(|| throw!("Invalid response"))?;
+                if !head.starts_with("HTTP/1.0 200") {
+                    return Err(throw!("Unexpected response: {}", head));
+                }
+                Ok(body.to_string())
+            }
+        }
+
+        fn serve_secret(path: &'static str, secret: &'static str) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8_lossy(&request);
+                let response = if request.starts_with(&format!("GET {} ", path)) {
+                    format!("HTTP/1.0 200 OK\r\n\r\n{}", secret)
+                } else {
+                    "HTTP/1.0 404 Not Found\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            });
+            address
+        }
+
+        #[test]
+        fn resolve_file_and_env_references() {
+            install_test_hooks();
+            let secret = TempFile::new("secret", "file-secret-value\n");
+            let env = EnvSecretProvider {
+                var: |name| (name == "SECRETS_TEST_VALUE").then(|| "env-secret-value".to_string()),
+            };
+
+            let file: Sensitive<String> = format!("secret+file://{}", secret.path().display())
+                .parse()
+                .unwrap();
+            let plain: Sensitive<String> = "postgres://db:5432".parse().unwrap();
+            let env_like: Sensitive<String> = "env:not-a-reference".parse().unwrap();
+            let literal: Sensitive<String> = "secret+literal:secret+env:value".parse().unwrap();
+
+            assert_eq!(file.as_str(), "file-secret-value");
+            assert_eq!(plain.as_str(), "postgres://db:5432");
+            assert_eq!(env_like.as_str(), "env:not-a-reference");
+            assert_eq!(literal.as_str(), "secret+env:value");
+            assert!("secret+unknown:value".parse::<Sensitive<String>>().is_err());
+            assert_eq!(scrub("file-secret-value"), MASK);
+            assert_eq!(
+                env.resolve("SECRETS_TEST_VALUE").unwrap(),
+                "env-secret-value"
+            );
+            assert!(env.resolve("SECRETS_TEST_MISSING").is_err());
+        }
+
+        #[test]
+        fn resolve_references_of_registered_provider() {
+            let address = serve_secret("/v1/payments/db", "http-secret-value");
+            register_secret_provider(HttpSecretProvider { address });
+
+            let secret: Sensitive<String> = "secret+http-test:payments/db".parse().unwrap();
+
+            assert_eq!(secret.as_str(), "http-secret-value");
+            assert_eq!(scrub("url=http-secret-value"), format!("url={}", MASK));
+        }
+    }
+}
@@ -1,19 +1,47 @@
-use std::fmt::Debug;
+use std::{ffi::OsString, fmt::Debug, sync::Arc};
+
+use tokio::sync::watch;
 
 pub mod build_info;
+mod config_command;
+mod config_reference;
+mod config_source;
+mod config_watch;
 mod core;
+mod error_reporting;
+mod feature;
 pub mod health_status;
+mod http;
 mod lang;
+mod log_output;
+mod propagation;
+mod resource;
+mod sampling;
 mod timeable;
 mod trace;
+mod trace_link;
 
+pub use crate::config_reference::{ConfigReference, ConfigReferenceEntry};
+pub use crate::config_source::ConfigSourceConfig;
 pub use crate::core::CoreConfig;
+pub use crate::error_reporting::report_error;
+pub use crate::feature::{Feature, FeatureId, FeatureRegistry, Features};
+pub use crate::http::HttpServerConfig;
+pub use crate::lang::secrets::{register_secret_provider, SecretProvider};
 #[allow(deprecated)]
-pub use crate::lang::sensitive::{Sensitive, SensitiveString};
+pub use crate::lang::sensitive::{ParseSensitiveError, Sensitive, SensitiveString};
+pub use crate::log_output::{
+    FileRotation, LogLineWriter, LogOutputConfig, LogWriter, TracingOutput,
+};
+pub use crate::propagation::{B3Propagator, TracePropagator};
+pub use crate::resource::{ResourceAttribute, ResourceConfig};
+pub use crate::sampling::{RuleSampler, SamplingRule};
 pub use crate::trace::{
-    HoneycombConfig, MakeSpanWithContext, RequestTracerPropagation, Tracing, TracingConfig,
-    TracingFormat, UuidMakeRequestId,
+    as_json, baggage, current_request_id, request_id_header, set_baggage, AsJson, JsonSchema,
+    LogFilter, MakeSpanWithContext, MakeSpanWithRedaction, RecordFailure, RecordResponse,
+    RequestTracerPropagation, Tracing, TracingConfig, TracingFormat, UuidMakeRequestId,
 };
+pub use crate::trace_link::{current_trace_link, TraceLinkConfig, TraceLinkProvider, TraceLinks};
 
 pub use async_trait::async_trait;
 pub use clap::{self, Args, Parser};
@@ -33,6 +61,11 @@ mod postgres;
 #[cfg(feature = "postgres")]
 pub use crate::postgres::{Postgres, PostgresConfig};
 
+#[cfg(feature = "sentry")]
+mod sentry_client;
+#[cfg(feature = "sentry")]
+pub use crate::sentry_client::SentryConfig;
+
 #[cfg(feature = "redis")]
 mod redis;
 
@@ -46,28 +79,17 @@ pub use streaming::{KafkaClient, KafkaConfig, Message, StreamingClient};
 
 pub use timeable::Timeable;
 
-// Feature enablement
-#[async_trait]
-pub trait Feature {
-    async fn init(service_name: &str, config: &EnvironmentConfig) -> Result<Self>
-    where
-        Self: Sized;
-}
-
 #[derive(Debug)]
 pub struct Environment<T: Debug + Args> {
     pub service_name: String,
     pub config: Config<T>,
     pub tracing: Tracing,
 
-    #[cfg(feature = "postgres")]
-    pub postgres: Postgres,
+    /// Project configuration, updated when `CONFIG_WATCH` is enabled and its files change.
+    pub project_updates: watch::Receiver<Arc<T>>,
 
-    #[cfg(feature = "redis")]
-    pub redis: Redis,
-
-    #[cfg(feature = "streaming")]
-    pub kafka: KafkaClient,
+    /// Features of the registry, retrieved by type, like `env.features.get::<Postgres>()`.
+    pub features: Features,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -76,19 +98,13 @@ pub struct EnvironmentConfig {
     pub core: core::CoreConfig,
 
     #[clap(flatten)]
-    pub tracing: TracingConfig,
-
-    #[cfg(feature = "postgres")]
-    #[clap(flatten)]
-    pub postgres: PostgresConfig,
+    pub source: ConfigSourceConfig,
 
-    #[cfg(feature = "redis")]
     #[clap(flatten)]
-    pub redis: RedisConfig,
+    pub tracing: TracingConfig,
 
-    #[cfg(feature = "streaming")]
     #[clap(flatten)]
-    pub kafka: KafkaConfig,
+    pub http: HttpServerConfig,
 }
 
 #[derive(Debug, Parser)]
@@ -101,32 +117,69 @@ pub struct Config<T: Debug + Args> {
 }
 
 impl<T: Debug + Args> Config<T> {
-    pub async fn init<S: AsRef<str>>(service_name: S) -> Result<Environment<T>> {
-        let Self {
-            project,
-            environment,
-        } = Self::parse();
+    /// Parses the configuration and initializes the features enabled by cargo features.
+    ///
+    /// Each value is taken from the first source setting it, in this order:
+    ///
+    /// 1. command line flags;
+    /// 2. environment variables;
+    /// 3. `.env` files (`--env-file`/`CONFIG_ENV_FILES`, `.env` by default);
+    /// 4. the TOML, YAML or JSON file given by `--config`/`CONFIG_FILE`;
+    /// 5. default values.
+    ///
+    /// When `CONFIG_WATCH` is enabled, the files are read again when they change or on `SIGHUP`,
+    /// and the new project configuration is published to [`Environment::project_updates`].
+    pub async fn init<S: AsRef<str>>(service_name: S) -> Result<Environment<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        Self::init_with_features(FeatureRegistry::new(), service_name).await
+    }
 
-        core::Core::init(service_name.as_ref(), &environment).await?;
-        timeable::init(service_name.as_ref());
+    /// Parses the configuration, including the configuration of every registered feature, and
+    /// initializes the features in dependency order.
+    pub async fn init_with_features<S: AsRef<str>>(
+        features: FeatureRegistry,
+        service_name: S,
+    ) -> Result<Environment<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let service_name = service_name.as_ref();
+        let args: Vec<OsString> = std::env::args_os().collect();
+        let command = features.augment_args(<Self as clap::CommandFactory>::command());
+        let loaded = match config_source::LoadedConfig::<Self>::load(command, &args) {
+            Ok(loaded) => loaded,
+            Err(report) => match report.downcast_ref::<clap::Error>() {
+                Some(error) => error.exit(),
+                None => return Err(report),
+            },
+        };
+        if let Some(command) = loaded.command {
+            std::process::exit(command.run(service_name, &loaded, &features).await);
+        }
+        let environment = &loaded.config.environment;
+
+        core::Core::init(service_name, environment).await?;
+        http::init_metrics(service_name, &environment.tracing.resource);
+        timeable::init(service_name);
+        let tracing = Tracing::init(service_name, environment).await?;
+        let project_updates = config_watch::watch_project(args, &loaded)?;
+        let features = features.init(service_name, &loaded.matches).await?;
 
         Ok(Environment {
-            service_name: service_name.as_ref().to_string(),
-            tracing: Tracing::init(service_name.as_ref(), &environment).await?,
-
-            #[cfg(feature = "postgres")]
-            postgres: Postgres::init(service_name.as_ref(), &environment).await?,
-
-            #[cfg(feature = "redis")]
-            redis: Redis::init(service_name.as_ref(), &environment).await?,
-
-            #[cfg(feature = "streaming")]
-            kafka: KafkaClient::new(&environment.kafka).await?,
-
-            config: Self {
-                project,
-                environment,
-            },
+            service_name: service_name.to_string(),
+            config: loaded.config,
+            tracing,
+            project_updates,
+            features,
         })
     }
 }
+
+impl<T: Debug + Args> Environment<T> {
+    /// Shuts down every feature, after the features depending on it.
+    pub async fn shutdown(&self) {
+        self.features.shutdown().await;
+    }
+}
@@ -0,0 +1,520 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{NaiveDateTime, Utc};
+use clap::ArgAction;
+use eyre::WrapErr;
+use metrics::{counter, describe_counter};
+use tracing_appender::{
+    non_blocking::{NonBlocking, NonBlockingBuilder, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::resource::metric_name;
+use crate::{Parser, Result};
+
+const METRIC_SUFFIX: &str = "log_dropped_lines";
+
+const FILE_EXTENSION: &str = "log";
+
+/// Timestamp of files rotated by size, which sorts chronologically.
+const ROTATED_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.6f";
+
+// -----------------------------------------------------------------------------
+// Supported Outputs
+// -----------------------------------------------------------------------------
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TracingOutput {
+    /// Events are written to standard output.
+    #[default]
+    Stdout,
+
+    /// Events are written to standard error.
+    Stderr,
+
+    /// Events are written to files in `TRACING_FILE_DIRECTORY`, rotated according to `TRACING_FILE_ROTATION`.
+    File,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FileRotation {
+    /// A new file is started every hour.
+    Hourly,
+
+    /// A new file is started every day.
+    #[default]
+    Daily,
+
+    /// A new file is started when the current one reaches `TRACING_FILE_MAX_SIZE` bytes.
+    Size,
+
+    /// Events are always appended to the same file.
+    Never,
+}
+
+// -----------------------------------------------------------------------------
+// Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Parser)]
+pub struct LogOutputConfig {
+    /// Where formatted events are written to.
+    #[clap(
+        value_enum,
+        long = "tracing-output",
+        env = "TRACING_OUTPUT",
+        default_value = "stdout"
+    )]
+    pub output: TracingOutput,
+
+    /// Directory of log files when output is `file`.
+    #[clap(
+        long = "tracing-file-directory",
+        env = "TRACING_FILE_DIRECTORY",
+        default_value = "logs"
+    )]
+    pub file_directory: PathBuf,
+
+    /// Name prefix of log files when output is `file`. Defaults to the service name.
+    #[clap(long = "tracing-file-prefix", env = "TRACING_FILE_PREFIX")]
+    pub file_prefix: Option<String>,
+
+    /// Rotation of log files when output is `file`.
+    #[clap(
+        value_enum,
+        long = "tracing-file-rotation",
+        env = "TRACING_FILE_ROTATION",
+        default_value = "daily"
+    )]
+    pub file_rotation: FileRotation,
+
+    /// Size in bytes a log file can reach before being rotated when rotation is `size`.
+    #[clap(
+        long = "tracing-file-max-size",
+        env = "TRACING_FILE_MAX_SIZE",
+        default_value = "104857600"
+    )]
+    pub file_max_size: u64,
+
+    /// Number of log files to keep, oldest are deleted on rotation. Keeps all files when not set.
+    #[clap(long = "tracing-file-max-files", env = "TRACING_FILE_MAX_FILES")]
+    pub file_max_files: Option<usize>,
+
+    /// Number of lines buffered before writing events waits for the background writer, or drops
+    /// them when `TRACING_OUTPUT_LOSSY` is set.
+    #[clap(
+        long = "tracing-buffered-lines",
+        env = "TRACING_BUFFERED_LINES",
+        default_value = "128000"
+    )]
+    pub buffered_lines: usize,
+
+    /// Whether events are dropped instead of waiting for the background writer when the buffer is
+    /// full.
+    #[clap(
+        long = "tracing-output-lossy",
+        env = "TRACING_OUTPUT_LOSSY",
+        default_value_t = false,
+        action = ArgAction::Set
+    )]
+    pub lossy: bool,
+}
+
+impl LogOutputConfig {
+    /// Whether ANSI colors can be written to the output.
+    pub fn supports_ansi(&self) -> bool {
+        self.output != TracingOutput::File
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Writer
+// -----------------------------------------------------------------------------
+
+/// Non-blocking writer used by formatter layers.
+///
+/// Events are sent to a bounded buffer consumed by a background thread. When the buffer is full,
+/// writing waits for the background thread, unless the output is lossy, in which case events are
+/// dropped and counted in the `<service>_log_dropped_lines` metric.
+#[derive(Clone, Debug)]
+pub struct LogWriter {
+    writer: NonBlocking,
+    dropped_lines: Arc<DroppedLines>,
+}
+
+/// Publishes the lines dropped by the background writer.
+#[derive(Debug)]
+struct DroppedLines {
+    metric_name: String,
+    reported: AtomicUsize,
+}
+
+impl LogWriter {
+    /// Starts the background writer for the configured output.
+    ///
+    /// The returned guard flushes buffered events when dropped, so it must be kept alive until shutdown.
+    pub fn init(service_name: &str, config: &LogOutputConfig) -> Result<(Self, WorkerGuard)> {
+        let output: Box<dyn Write + Send> = match config.output {
+            TracingOutput::Stdout => Box::new(io::stdout()),
+            TracingOutput::Stderr => Box::new(io::stderr()),
+            TracingOutput::File => {
+                let prefix = config.file_prefix.as_deref().unwrap_or(service_name);
+                file_writer(config, prefix)?
+            }
+        };
+
+        let (writer, guard) = NonBlockingBuilder::default()
+            .buffered_lines_limit(config.buffered_lines)
+            .lossy(config.lossy)
+            .thread_name("log-writer")
+            .finish(output);
+
+        let metric_name = metric_name(service_name, METRIC_SUFFIX);
+        describe_counter!(
+            metric_name.clone(),
+            "Log lines dropped because the log writer buffer was full."
+        );
+
+        let writer = Self {
+            writer,
+            dropped_lines: Arc::new(DroppedLines {
+                metric_name,
+                reported: AtomicUsize::new(0),
+            }),
+        };
+        Ok((writer, guard))
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LogLineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogLineWriter {
+            writer: self.writer.clone(),
+            dropped_lines: self.dropped_lines.clone(),
+        }
+    }
+}
+
+/// Writes an event to the background writer, publishing it right away if it is dropped.
+#[derive(Debug)]
+pub struct LogLineWriter {
+    writer: NonBlocking,
+    dropped_lines: Arc<DroppedLines>,
+}
+
+impl Write for LogLineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf);
+        self.dropped_lines
+            .report(self.writer.error_counter().dropped_lines());
+        written
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl DroppedLines {
+    /// Publishes lines dropped since the last report.
+    fn report(&self, dropped: usize) {
+        let reported = self.reported.fetch_max(dropped, Ordering::Relaxed);
+        if dropped > reported {
+            counter!(self.metric_name.clone(), (dropped - reported) as u64);
+        }
+    }
+}
+
+fn file_writer(config: &LogOutputConfig, prefix: &str) -> Result<Box<dyn Write + Send>> {
+    let rotation = match config.file_rotation {
+        FileRotation::Hourly => Rotation::HOURLY,
+        FileRotation::Daily => Rotation::DAILY,
+        FileRotation::Never => Rotation::NEVER,
+        FileRotation::Size => {
+            let writer = SizeRollingWriter::new(
+                &config.file_directory,
+                prefix,
+                config.file_max_size,
+                config.file_max_files,
+            )?;
+            return Ok(Box::new(writer));
+        }
+    };
+
+    let mut builder = RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(prefix)
+        .filename_suffix(FILE_EXTENSION);
+    if let Some(max_files) = config.file_max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    let appender = builder
+        .build(&config.file_directory)
+        .wrap_err("Failed to create log file")?;
+    Ok(Box::new(appender))
+}
+
+// -----------------------------------------------------------------------------
+// Size Rolling Writer
+// -----------------------------------------------------------------------------
+
+/// Writes to `<prefix>.log`, renaming it to `<prefix>.<timestamp>.log` when it reaches the maximum size.
+#[derive(Debug)]
+struct SizeRollingWriter {
+    directory: PathBuf,
+    prefix: String,
+    max_size: u64,
+    max_files: Option<usize>,
+    file: File,
+    size: u64,
+}
+
+impl SizeRollingWriter {
+    fn new(
+        directory: &Path,
+        prefix: &str,
+        max_size: u64,
+        max_files: Option<usize>,
+    ) -> Result<Self> {
+        fs::create_dir_all(directory).wrap_err("Failed to create log directory")?;
+        let path = directory.join(format!("{}.{}", prefix, FILE_EXTENSION));
+        let file = open_append(&path).wrap_err("Failed to create log file")?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            prefix: prefix.to_string(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn current_path(&self) -> PathBuf {
+        self.directory
+            .join(format!("{}.{}", self.prefix, FILE_EXTENSION))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let timestamp = Utc::now().format(ROTATED_TIMESTAMP_FORMAT);
+        let rotated = self
+            .directory
+            .join(format!("{}.{}.{}", self.prefix, timestamp, FILE_EXTENSION));
+        fs::rename(self.current_path(), rotated)?;
+
+        self.file = open_append(&self.current_path())?;
+        self.size = 0;
+
+        self.prune()
+    }
+
+    /// Deletes the oldest rotated files so that at most `max_files` files exist, including the current one.
+    fn prune(&self) -> io::Result<()> {
+        let Some(max_files) = self.max_files else {
+            return Ok(());
+        };
+
+        let mut rotated: Vec<_> = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| self.is_rotated(name))
+            .collect();
+
+        // timestamps sort chronologically, so the oldest files come first
+        rotated.sort();
+        let keep = max_files.saturating_sub(1);
+        let excess = rotated.len().saturating_sub(keep);
+        for name in &rotated[..excess] {
+            fs::remove_file(self.directory.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+impl SizeRollingWriter {
+    /// Whether the file was rotated by this writer, so files of other prefixes sharing the same
+    /// beginning, like `api.v2.log` for `api`, are never deleted.
+    fn is_rotated(&self, name: &str) -> bool {
+        let timestamp = name
+            .strip_prefix(&self.prefix)
+            .and_then(|name| name.strip_prefix('.'))
+            .and_then(|name| name.strip_suffix(FILE_EXTENSION))
+            .and_then(|name| name.strip_suffix('.'));
+        match timestamp {
+            Some(timestamp) => {
+                NaiveDateTime::parse_from_str(timestamp, ROTATED_TIMESTAMP_FORMAT).is_ok()
+            }
+            None => false,
+        }
+    }
+}
+
+impl Write for SizeRollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, path::Path};
+
+    use chrono::{NaiveDate, NaiveDateTime};
+    use clap::Parser;
+    use uuid::Uuid;
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::{file_writer, LogOutputConfig, LogWriter, SizeRollingWriter};
+
+    fn file_names(directory: &Path) -> Vec<String> {
+        let mut files: Vec<_> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Writes a line with the given rotation, returning the middle of the name of the only file.
+    fn rotated_name(rotation: &str) -> String {
+        let directory = std::env::temp_dir().join(format!("balthazar-logs-{}", Uuid::new_v4()));
+        let config = LogOutputConfig::parse_from([
+            "test".to_string(),
+            "--tracing-output=file".to_string(),
+            format!("--tracing-file-directory={}", directory.display()),
+            format!("--tracing-file-rotation={}", rotation),
+        ]);
+
+        let mut writer = file_writer(&config, "service").unwrap();
+        writer.write_all(b"line\n").unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let files = file_names(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(files.len(), 1);
+        files[0]
+            .strip_prefix("service")
+            .and_then(|name| name.strip_suffix("log"))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn log_writer_keeps_every_line_unless_lossy() {
+        let directory = std::env::temp_dir().join(format!("balthazar-logs-{}", Uuid::new_v4()));
+        let config = LogOutputConfig::parse_from([
+            "test".to_string(),
+            "--tracing-output=file".to_string(),
+            format!("--tracing-file-directory={}", directory.display()),
+            "--tracing-file-rotation=never".to_string(),
+            "--tracing-buffered-lines=1".to_string(),
+        ]);
+        assert!(!config.lossy);
+
+        let (writer, guard) = LogWriter::init("service", &config).unwrap();
+        for line in 0..1000 {
+            writeln!(writer.make_writer(), "line {}", line).unwrap();
+        }
+        drop(guard);
+
+        let lines = fs::read_to_string(directory.join("service.log")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(lines.lines().count(), 1000);
+    }
+
+    #[test]
+    fn size_rolling_writer_rotates_and_keeps_max_files() {
+        let directory = std::env::temp_dir().join(format!("balthazar-logs-{}", Uuid::new_v4()));
+        let mut writer = SizeRollingWriter::new(&directory, "service", 10, Some(3)).unwrap();
+
+        for line in ["line 1\n", "line 2\n", "line 3\n", "line 4\n", "line 5\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let files = file_names(&directory);
+        assert_eq!(files.len(), 3);
+        assert!(files.contains(&"service.log".to_string()));
+        assert_eq!(
+            fs::read_to_string(directory.join("service.log")).unwrap(),
+            "line 5\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join(&files[0])).unwrap(),
+            "line 3\n"
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn size_rolling_writer_keeps_files_of_other_prefixes() {
+        let directory = std::env::temp_dir().join(format!("balthazar-logs-{}", Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        let neighbours = [
+            "api.v2.log",
+            "api.v2.20240101T000000.000000.log",
+            "api.old.log",
+        ];
+        for name in neighbours {
+            fs::write(directory.join(name), "other service\n").unwrap();
+        }
+
+        let mut writer = SizeRollingWriter::new(&directory, "api", 10, Some(2)).unwrap();
+        for line in ["line 1\n", "line 2\n", "line 3\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let files = file_names(&directory);
+        assert_eq!(files.len(), neighbours.len() + 2);
+        for name in neighbours {
+            assert!(files.contains(&name.to_string()));
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn file_writer_names_files_by_rotation() {
+        assert_eq!(rotated_name("never"), ".");
+
+        let daily = rotated_name("daily");
+        let date = daily.trim_matches('.');
+        assert!(NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok());
+
+        let hourly = rotated_name("hourly");
+        let hour = format!("{}:00", hourly.trim_matches('.'));
+        assert!(NaiveDateTime::parse_from_str(&hour, "%Y-%m-%d-%H:%M").is_ok());
+    }
+}
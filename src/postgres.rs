@@ -7,9 +7,11 @@ use crate::*;
 
 #[derive(Debug, Clone, Parser)]
 pub struct PostgresConfig {
-    #[clap(long = "postgres-url", env = "POSTGRES_URL")]
+    /// Connection string of the PostgreSQL instance.
+    #[clap(id = "postgres_url", long = "postgres-url", env = "POSTGRES_URL")]
     pub url: Sensitive<String>,
 
+    /// Maximum number of concurrent connections of the pool.
     #[clap(
         long = "postgres-max-connections",
         env = "POSTGRES_MAX_CONNECTIONS",
@@ -25,14 +27,35 @@ pub struct Postgres {
 
 #[crate::async_trait]
 impl Feature for Postgres {
-    async fn init(_service_name: &str, config: &EnvironmentConfig) -> Result<Self> {
+    type Config = PostgresConfig;
+    const NAME: &'static str = "postgres";
+    const TITLE: &'static str = "PostgreSQL";
+    const CARGO_FEATURE: Option<&'static str> = Some("postgres");
+
+    async fn init(_service_name: &str, config: &PostgresConfig, _: &Features) -> Result<Self> {
         Ok(Self {
             pool: PgPoolOptions::new()
-                .max_connections(config.postgres.pool_max_connections)
-                .connect(&config.postgres.url)
+                .max_connections(config.pool_max_connections)
+                .connect(&config.url)
                 .await?,
         })
     }
+
+    async fn health_check(&self) -> Result<()> {
+        Postgres::health_check(self).await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.pool.close().await;
+        Ok(())
+    }
+}
+
+impl Postgres {
+    pub async fn health_check(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
 }
 
 impl Deref for Postgres {
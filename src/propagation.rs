@@ -0,0 +1,312 @@
+use opentelemetry::propagation::{
+    text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator,
+};
+use opentelemetry::sdk::propagation::{
+    BaggagePropagator, TextMapCompositePropagator, TraceContextPropagator,
+};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+
+const B3_SINGLE_HEADER: &str = "b3";
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+
+/// Flag of remote span contexts without a sampling decision, left to the local sampler.
+pub(crate) const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
+
+// -----------------------------------------------------------------------------
+// Supported Propagators
+// -----------------------------------------------------------------------------
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TracePropagator {
+    /// W3C Trace Context, with `traceparent` and `tracestate` headers.
+    #[value(name = "tracecontext")]
+    TraceContext,
+
+    /// W3C Baggage, with the `baggage` header.
+    Baggage,
+
+    /// Zipkin B3 single header, with the `b3` header, used by Envoy and Istio.
+    B3,
+
+    /// Zipkin B3 multiple headers, with `x-b3-traceid`, `x-b3-spanid` and `x-b3-sampled` headers.
+    #[value(name = "b3multi")]
+    B3Multi,
+
+    /// Jaeger, with the `uber-trace-id` header.
+    Jaeger,
+}
+
+/// Creates a propagator injecting and extracting the context with all the given propagators.
+///
+/// When extracting, propagators later in the list take precedence over earlier ones.
+pub(crate) fn composite_propagator(propagators: &[TracePropagator]) -> TextMapCompositePropagator {
+    let mut selected: Vec<TracePropagator> = Vec::new();
+    for propagator in propagators {
+        if !selected.contains(propagator) {
+            selected.push(*propagator);
+        }
+    }
+
+    let propagators = selected
+        .into_iter()
+        .map(|propagator| -> Box<dyn TextMapPropagator + Send + Sync> {
+            match propagator {
+                TracePropagator::TraceContext => Box::new(TraceContextPropagator::new()),
+                TracePropagator::Baggage => Box::new(BaggagePropagator::new()),
+                TracePropagator::B3 => Box::new(B3Propagator::single_header()),
+                TracePropagator::B3Multi => Box::new(B3Propagator::multiple_headers()),
+                TracePropagator::Jaeger => Box::new(opentelemetry_jaeger::Propagator::new()),
+            }
+        })
+        .collect();
+    TextMapCompositePropagator::new(propagators)
+}
+
+// -----------------------------------------------------------------------------
+// B3 Propagator
+// -----------------------------------------------------------------------------
+
+/// Propagates the context using [Zipkin B3](https://github.com/openzipkin/b3-propagation) headers.
+///
+/// Both encodings are accepted when extracting, but only the configured one is injected.
+#[derive(Clone, Debug)]
+pub struct B3Propagator {
+    single_header: bool,
+    fields: Vec<String>,
+}
+
+impl B3Propagator {
+    /// Injects the context in the `b3` header.
+    pub fn single_header() -> Self {
+        Self {
+            single_header: true,
+            fields: vec![B3_SINGLE_HEADER.to_string()],
+        }
+    }
+
+    /// Injects the context in the `x-b3-traceid`, `x-b3-spanid` and `x-b3-sampled` headers.
+    pub fn multiple_headers() -> Self {
+        Self {
+            single_header: false,
+            fields: [B3_TRACE_ID_HEADER, B3_SPAN_ID_HEADER, B3_SAMPLED_HEADER]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+
+    fn extract_single_header(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        let header = extractor.get(B3_SINGLE_HEADER)?.trim();
+        let mut parts = header.split('-');
+        let trace_id = parse_trace_id(parts.next()?)?;
+        let span_id = SpanId::from_hex(parts.next()?).ok()?;
+        let sampled = match parts.next() {
+            Some(sampled) => Some(parse_sampled(sampled)?),
+            None => None,
+        };
+        span_context(trace_id, span_id, sampled)
+    }
+
+    fn extract_multiple_headers(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        let trace_id = parse_trace_id(extractor.get(B3_TRACE_ID_HEADER)?.trim())?;
+        let span_id = SpanId::from_hex(extractor.get(B3_SPAN_ID_HEADER)?.trim()).ok()?;
+        let debug = extractor.get(B3_FLAGS_HEADER).map(str::trim) == Some("1");
+        let sampled = match extractor.get(B3_SAMPLED_HEADER) {
+            Some(sampled) => Some(parse_sampled(sampled.trim())?),
+            None => None,
+        };
+        span_context(trace_id, span_id, if debug { Some(true) } else { sampled })
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+
+        let trace_id = format!("{:032x}", span_context.trace_id());
+        let span_id = format!("{:016x}", span_context.span_id());
+        let sampled = if span_context.is_sampled() {
+            Some("1")
+        } else if span_context.is_remote()
+            && span_context.trace_flags() & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED
+        {
+            None
+        } else {
+            Some("0")
+        };
+        if self.single_header {
+            let header = match sampled {
+                Some(sampled) => format!("{}-{}-{}", trace_id, span_id, sampled),
+                None => format!("{}-{}", trace_id, span_id),
+            };
+            injector.set(B3_SINGLE_HEADER, header);
+        } else {
+            injector.set(B3_TRACE_ID_HEADER, trace_id);
+            injector.set(B3_SPAN_ID_HEADER, span_id);
+            if let Some(sampled) = sampled {
+                injector.set(B3_SAMPLED_HEADER, sampled.to_string());
+            }
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        self.extract_single_header(extractor)
+            .or_else(|| self.extract_multiple_headers(extractor))
+            .map(|span_context| cx.with_remote_span_context(span_context))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+/// Parses 64 or 128 bits trace ids, 64 bits ones are left padded with zeros.
+fn parse_trace_id(trace_id: &str) -> Option<TraceId> {
+    match trace_id.len() {
+        16 | 32 => TraceId::from_hex(trace_id).ok(),
+        _ => None,
+    }
+}
+
+/// Parses the sampling state, debug (`d`) is sampled.
+fn parse_sampled(sampled: &str) -> Option<bool> {
+    match sampled {
+        "1" | "d" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Creates a remote span context, flagged as deferred when the sampling state is missing.
+fn span_context(trace_id: TraceId, span_id: SpanId, sampled: Option<bool>) -> Option<SpanContext> {
+    let flags = match sampled {
+        Some(true) => TraceFlags::SAMPLED,
+        Some(false) => TraceFlags::default(),
+        None => TRACE_FLAG_DEFERRED,
+    };
+    let span_context = SpanContext::new(trace_id, span_id, flags, true, TraceState::default());
+    span_context.is_valid().then_some(span_context)
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::{SpanContext, TraceContextExt};
+
+    use super::{composite_propagator, B3Propagator, TracePropagator, TRACE_FLAG_DEFERRED};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const SPAN_ID: &str = "00f067aa0ba902b7";
+
+    fn headers(headers: &[(&str, &str)]) -> HashMap<String, String> {
+        headers
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn extracted(
+        propagator: &dyn TextMapPropagator,
+        carrier: &HashMap<String, String>,
+    ) -> SpanContext {
+        propagator.extract(carrier).span().span_context().clone()
+    }
+
+    #[test]
+    fn b3_propagator_round_trip() {
+        for propagator in [
+            B3Propagator::single_header(),
+            B3Propagator::multiple_headers(),
+        ] {
+            let carrier = headers(&[("b3", &format!("{}-{}-1", TRACE_ID, SPAN_ID))]);
+            let context = propagator.extract(&carrier);
+
+            let mut injected = HashMap::new();
+            propagator.inject_context(&context, &mut injected);
+
+            let span_context = extracted(&propagator, &injected);
+            assert_eq!(format!("{:032x}", span_context.trace_id()), TRACE_ID);
+            assert_eq!(format!("{:016x}", span_context.span_id()), SPAN_ID);
+            assert!(span_context.is_sampled());
+        }
+    }
+
+    #[test]
+    fn b3_propagator_extracts_multiple_headers() {
+        let carrier = headers(&[
+            ("x-b3-traceid", "a3ce929d0e0e4736"),
+            ("x-b3-spanid", SPAN_ID),
+            ("x-b3-flags", "1"),
+        ]);
+
+        let span_context = extracted(&B3Propagator::single_header(), &carrier);
+
+        assert_eq!(
+            format!("{:032x}", span_context.trace_id()),
+            "0000000000000000a3ce929d0e0e4736"
+        );
+        assert!(span_context.is_sampled());
+        assert!(span_context.is_remote());
+    }
+
+    #[test]
+    fn b3_propagator_defers_missing_sampling_state() {
+        let propagator = B3Propagator::multiple_headers();
+        for carrier in [
+            headers(&[("b3", &format!("{}-{}", TRACE_ID, SPAN_ID))]),
+            headers(&[("x-b3-traceid", TRACE_ID), ("x-b3-spanid", SPAN_ID)]),
+        ] {
+            let span_context = extracted(&propagator, &carrier);
+
+            assert!(span_context.is_valid());
+            assert!(!span_context.is_sampled());
+            assert_eq!(span_context.trace_flags(), TRACE_FLAG_DEFERRED);
+
+            let mut injected = HashMap::new();
+            propagator.inject_context(&propagator.extract(&carrier), &mut injected);
+            assert!(!injected.contains_key("x-b3-sampled"));
+        }
+    }
+
+    #[test]
+    fn composite_propagator_extracts_any_selected_format() {
+        let propagator = composite_propagator(&[
+            TracePropagator::TraceContext,
+            TracePropagator::B3,
+            TracePropagator::Jaeger,
+        ]);
+
+        let b3 = headers(&[("b3", &format!("{}-{}-0", TRACE_ID, SPAN_ID))]);
+        assert_eq!(
+            format!("{:032x}", extracted(&propagator, &b3).trace_id()),
+            TRACE_ID
+        );
+
+        let jaeger = headers(&[("uber-trace-id", &format!("{}:{}:0:1", TRACE_ID, SPAN_ID))]);
+        assert_eq!(
+            format!("{:032x}", extracted(&propagator, &jaeger).trace_id()),
+            TRACE_ID
+        );
+
+        let mut injected = HashMap::new();
+        propagator.inject_context(&propagator.extract(&b3), &mut injected);
+        assert!(injected.contains_key("traceparent"));
+        assert!(injected.contains_key("b3"));
+        assert!(injected.contains_key("uber-trace-id"));
+
+        let none = composite_propagator(&[TracePropagator::TraceContext]);
+        assert!(!extracted(&none, &b3).is_valid());
+    }
+}
@@ -2,11 +2,12 @@ use std::ops::Deref;
 
 use crate::*;
 
-use bb8_redis::{bb8::Pool, RedisMultiplexedConnectionManager};
+use bb8_redis::{bb8::Pool, redis, RedisMultiplexedConnectionManager};
 
 #[derive(Debug, Clone, Parser)]
 pub struct RedisConfig {
-    #[clap(long = "redis-url", env = "REDIS_URL")]
+    /// Connection string of the Redis instance.
+    #[clap(id = "redis_url", long = "redis-url", env = "REDIS_URL")]
     pub url: Sensitive<String>,
 }
 
@@ -17,14 +18,33 @@ pub struct Redis {
 
 #[async_trait]
 impl Feature for Redis {
-    async fn init(_service_name: &str, config: &EnvironmentConfig) -> Result<Self> {
-        let manager = RedisMultiplexedConnectionManager::new(config.redis.url.0.clone())?;
+    type Config = RedisConfig;
+    const NAME: &'static str = "redis";
+    const TITLE: &'static str = "Redis";
+    const CARGO_FEATURE: Option<&'static str> = Some("redis");
+
+    async fn init(_service_name: &str, config: &RedisConfig, _: &Features) -> Result<Self> {
+        let manager = RedisMultiplexedConnectionManager::new(config.url.0.clone())?;
         let connection_pool = Pool::builder().build(manager).await?;
 
         Ok(Self {
             pool: connection_pool,
         })
     }
+
+    async fn health_check(&self) -> Result<()> {
+        Redis::health_check(self).await
+    }
+}
+
+impl Redis {
+    pub async fn health_check(&self) -> Result<()> {
+        let mut connection = self.pool.get().await?;
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut *connection)
+            .await?;
+        Ok(())
+    }
 }
 
 impl Deref for Redis {
@@ -0,0 +1,305 @@
+use std::str::FromStr;
+
+use opentelemetry::sdk::Resource;
+use opentelemetry::KeyValue;
+
+use crate::build_info::{self, BuildInfo};
+use crate::Parser;
+
+/// Attributes added as labels to every metric, because they are the same for every pod and deploy.
+const METRIC_LABEL_ATTRIBUTES: &[&str] = &["service.name", "deployment.environment"];
+
+/// Attributes published as labels of the `<service>_build_info` metric only.
+const BUILD_INFO_ATTRIBUTES: &[&str] = &["service.version", "git.commit.sha", "git.branch"];
+
+// -----------------------------------------------------------------------------
+// Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Parser)]
+pub struct ResourceConfig {
+    /// Deployment environment, like `staging` or `production`.
+    #[clap(long = "tracing-environment", env = "ENVIRONMENT")]
+    pub environment: Option<String>,
+
+    /// Kubernetes pod name, usually set from `metadata.name` through the downward API.
+    #[clap(long = "tracing-k8s-pod-name", env = "K8S_POD_NAME")]
+    pub k8s_pod_name: Option<String>,
+
+    /// Kubernetes namespace, usually set from `metadata.namespace` through the downward API.
+    #[clap(long = "tracing-k8s-namespace", env = "K8S_NAMESPACE")]
+    pub k8s_namespace: Option<String>,
+
+    /// Kubernetes node name, usually set from `spec.nodeName` through the downward API.
+    #[clap(long = "tracing-k8s-node-name", env = "K8S_NODE_NAME")]
+    pub k8s_node_name: Option<String>,
+
+    /// Host name. Defaults to the name reported by the operating system.
+    #[clap(long = "tracing-host-name", env = "HOSTNAME")]
+    pub host_name: Option<String>,
+
+    /// Comma-separated `<key>=<value>` attributes describing the service, overriding the
+    /// attributes detected from the build and the environment.
+    #[clap(
+        long = "tracing-resource-attributes",
+        env = "TRACING_RESOURCE_ATTRIBUTES",
+        value_delimiter = ','
+    )]
+    pub resource_attributes: Vec<ResourceAttribute>,
+
+    /// Comma-separated attribute keys added as labels to every metric, in addition to
+    /// `service.name` and `deployment.environment`. Attributes changing with every pod or deploy,
+    /// like `k8s.pod.name`, create new series each time.
+    #[clap(
+        long = "tracing-metric-label-attributes",
+        env = "TRACING_METRIC_LABEL_ATTRIBUTES",
+        value_delimiter = ','
+    )]
+    pub metric_label_attributes: Vec<String>,
+}
+
+impl ResourceConfig {
+    /// Attributes describing the service, taken from the registered [`BuildInfo`], the environment
+    /// and `TRACING_RESOURCE_ATTRIBUTES`, in increasing order of precedence.
+    pub fn attributes(&self) -> Vec<(String, String)> {
+        self.attributes_with(build_info::current())
+    }
+
+    /// OpenTelemetry resource with the SDK detected attributes and [`ResourceConfig::attributes`].
+    pub fn resource(&self) -> Resource {
+        let attributes = self
+            .attributes()
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value));
+        Resource::default().merge(&Resource::new(attributes))
+    }
+
+    /// Attributes added to every metric, as Prometheus label names and values: `service.name`,
+    /// `deployment.environment` and the ones listed in `TRACING_METRIC_LABEL_ATTRIBUTES`.
+    pub fn metric_labels(&self) -> Vec<(String, String)> {
+        self.metric_labels_of(self.attributes())
+    }
+
+    /// Build attributes, like `service.version`, as Prometheus label names and values.
+    pub fn build_info_labels(&self) -> Vec<(String, String)> {
+        labels_of(self.attributes(), |key| {
+            BUILD_INFO_ATTRIBUTES.contains(&key)
+        })
+    }
+
+    fn metric_labels_of(&self, attributes: Vec<(String, String)>) -> Vec<(String, String)> {
+        labels_of(attributes, |key| {
+            METRIC_LABEL_ATTRIBUTES.contains(&key)
+                || self
+                    .metric_label_attributes
+                    .iter()
+                    .any(|label| label == key)
+        })
+    }
+
+    fn attributes_with(&self, build_info: Option<&BuildInfo>) -> Vec<(String, String)> {
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let mut insert = |key: &str, value: Option<String>| {
+            let Some(value) = value.filter(|value| !value.is_empty()) else {
+                return;
+            };
+            attributes.retain(|(existing, _)| existing != key);
+            attributes.push((key.to_string(), value));
+        };
+
+        if let Some(build_info) = build_info {
+            insert("service.version", Some(build_info.version.to_string()));
+            insert(
+                "git.commit.sha",
+                Some(build_info.git.commit_hash.to_string()),
+            );
+            insert("git.branch", Some(build_info.git.branch.to_string()));
+        }
+
+        insert("deployment.environment", self.environment.clone());
+        insert("k8s.pod.name", self.k8s_pod_name.clone());
+        insert("k8s.namespace.name", self.k8s_namespace.clone());
+        insert("k8s.node.name", self.k8s_node_name.clone());
+        insert(
+            "host.name",
+            self.host_name.clone().or_else(|| {
+                hostname::get()
+                    .ok()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }),
+        );
+
+        for attribute in &self.resource_attributes {
+            insert(&attribute.key, Some(attribute.value.clone()));
+        }
+        attributes
+    }
+}
+
+fn labels_of(
+    attributes: Vec<(String, String)>,
+    selected: impl Fn(&str) -> bool,
+) -> Vec<(String, String)> {
+    attributes
+        .into_iter()
+        .filter(|(key, _)| selected(key))
+        .map(|(key, value)| (metric_label(&key), value))
+        .collect()
+}
+
+/// Converts an attribute key, like `k8s.pod.name`, to a valid Prometheus label name.
+fn metric_label(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Name of a metric of the service, like `payments_api_build_info` for `payments-api`, valid for
+/// Prometheus whatever the service name.
+pub(crate) fn metric_name(service_name: &str, suffix: &str) -> String {
+    let name = metric_label(&format!("{}_{}", service_name, suffix));
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Attributes
+// -----------------------------------------------------------------------------
+
+/// Attribute describing the service, written as `<key>=<value>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceAttribute {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for ResourceAttribute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(Self {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(format!("resource attribute must be <key>=<value>: {}", s)),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use crate::build_info::{BuildInfo, GitInfo, RustInfo};
+    use crate::Parser;
+
+    use super::{metric_label, metric_name, ResourceConfig};
+
+    fn build_info() -> BuildInfo {
+        BuildInfo {
+            version: "0.3.1",
+            datetime: "2023-01-01T00:00:00Z",
+            rust: RustInfo {
+                version: "1.70.0",
+                platform: "x86_64-unknown-linux-gnu",
+                features: "default",
+                profile: "release",
+            },
+            git: GitInfo {
+                branch: "main",
+                commit_author_email: "dev@example.com",
+                commit_author_name: "dev",
+                commit_hash: "abc123",
+                commit_datetime: "2023-01-01T00:00:00Z",
+                commit_message: "initial commit",
+            },
+        }
+    }
+
+    #[test]
+    fn resource_attributes_from_build_environment_and_config() {
+        let config = ResourceConfig::parse_from([
+            "test",
+            "--tracing-environment",
+            "production",
+            "--tracing-k8s-pod-name",
+            "service-6f7d9",
+            "--tracing-host-name",
+            "node-1",
+            "--tracing-resource-attributes",
+            "team=payments,service.version=1.2.0",
+        ]);
+
+        let attributes = config.attributes_with(Some(&build_info()));
+        let get = |key: &str| {
+            attributes
+                .iter()
+                .find(|(existing, _)| existing == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(get("service.version"), Some("1.2.0"));
+        assert_eq!(get("git.commit.sha"), Some("abc123"));
+        assert_eq!(get("deployment.environment"), Some("production"));
+        assert_eq!(get("k8s.pod.name"), Some("service-6f7d9"));
+        assert_eq!(get("host.name"), Some("node-1"));
+        assert_eq!(get("team"), Some("payments"));
+        assert_eq!(
+            attributes
+                .iter()
+                .filter(|(key, _)| key == "service.version")
+                .count(),
+            1
+        );
+
+        // the version of the crate, not its commit, unless overridden
+        let attributes = ResourceConfig::parse_from(["test"]).attributes_with(Some(&build_info()));
+        assert!(attributes.contains(&("service.version".to_string(), "0.3.1".to_string())));
+    }
+
+    #[test]
+    fn metric_labels_exclude_attributes_of_pods_and_deploys() {
+        let config = ResourceConfig::parse_from([
+            "test",
+            "--tracing-environment",
+            "production",
+            "--tracing-k8s-pod-name",
+            "service-6f7d9",
+            "--tracing-resource-attributes",
+            "team=payments,region=us-east-1",
+            "--tracing-metric-label-attributes",
+            "team",
+        ]);
+
+        let labels = config.metric_labels_of(config.attributes_with(Some(&build_info())));
+        assert_eq!(
+            labels,
+            vec![
+                (
+                    "deployment_environment".to_string(),
+                    "production".to_string()
+                ),
+                ("team".to_string(), "payments".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resource_attribute_keys_as_metric_labels() {
+        assert_eq!(metric_label("k8s.pod.name"), "k8s_pod_name");
+        assert_eq!(
+            metric_name("payments-api", "build_info"),
+            "payments_api_build_info"
+        );
+        assert_eq!(
+            metric_name("3ds.proxy", "build_info"),
+            "_3ds_proxy_build_info"
+        );
+        assert!("novalue".parse::<super::ResourceAttribute>().is_err());
+        assert!("=value".parse::<super::ResourceAttribute>().is_err());
+    }
+}
@@ -0,0 +1,378 @@
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use opentelemetry::sdk::trace::{Sampler, ShouldSample};
+use opentelemetry::trace::{Link, OrderMap, SamplingResult, SpanKind, TraceContextExt, TraceId};
+use opentelemetry::{Context, InstrumentationLibrary, Key, Value};
+
+use crate::propagation::TRACE_FLAG_DEFERRED;
+
+/// Span attributes that mark a span as an error.
+const ERROR_ATTRIBUTES: [&str; 2] = ["error", "exception.message"];
+
+/// Span attributes holding HTTP status codes, server errors mark a span as an error.
+const STATUS_CODE_ATTRIBUTES: [&str; 2] = ["status_code", "http.status_code"];
+
+static ALWAYS_ON: Lazy<Sampler> = Lazy::new(|| Sampler::AlwaysOn);
+
+static ALWAYS_OFF: Lazy<Sampler> = Lazy::new(|| Sampler::AlwaysOff);
+
+/// Follows the parent decision, the delegate is never used because it is only selected for spans
+/// with a parent.
+static PARENT: Lazy<Sampler> = Lazy::new(|| Sampler::ParentBased(Box::new(Sampler::AlwaysOff)));
+
+// -----------------------------------------------------------------------------
+// Rules
+// -----------------------------------------------------------------------------
+
+/// Samples a ratio of root spans whose name matches a route, written as `<route>=<ratio>`.
+///
+/// The route matches span names created by [`MakeSpanWithContext`](crate::MakeSpanWithContext)
+/// either entirely, like `GET /users/:id`, or without the method, like `/users/:id`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SamplingRule {
+    pub route: String,
+    pub ratio: f64,
+}
+
+impl SamplingRule {
+    fn matches(&self, name: &str) -> bool {
+        route_matches(&self.route, name)
+    }
+}
+
+impl FromStr for SamplingRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (route, ratio) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("sampling rule must be <route>=<ratio>: {}", s))?;
+        let ratio = ratio
+            .trim()
+            .parse()
+            .map_err(|_| format!("sampling rule ratio must be a number: {}", s))?;
+        Ok(Self {
+            route: route.trim().to_string(),
+            ratio,
+        })
+    }
+}
+
+fn route_matches(route: &str, name: &str) -> bool {
+    name == route
+        || name
+            .split_once(' ')
+            .is_some_and(|(_, name_route)| name_route == route)
+}
+
+// -----------------------------------------------------------------------------
+// Sampler
+// -----------------------------------------------------------------------------
+
+/// Sampler deciding in order:
+///
+/// 1. spans with a parent follow the parent decision, so traces are kept whole, unless the parent
+///    comes from another service that deferred the decision, like B3 headers without sampling state;
+/// 2. other spans with an error attribute are sampled, if enabled;
+/// 3. other spans of dropped routes, like health checks, are not sampled;
+/// 4. other spans matching a [`SamplingRule`] are sampled by the rule ratio;
+/// 5. other spans are sampled by the default ratio.
+///
+/// The decision is made when the span is created, so only the attributes it is created with are
+/// taken into account. Errors recorded later, like the status code recorded by
+/// [`RecordResponse`](crate::RecordResponse), do not change it.
+#[derive(Clone, Debug)]
+pub struct RuleSampler {
+    default: Sampler,
+    rules: Vec<(SamplingRule, Sampler)>,
+    drop_routes: Vec<String>,
+    sample_errors: bool,
+}
+
+impl RuleSampler {
+    /// Creates a sampler sampling every root span, or the given ratio of them.
+    pub fn new(ratio: Option<f64>) -> Self {
+        Self {
+            default: ratio_sampler(ratio),
+            rules: Vec::new(),
+            drop_routes: Vec::new(),
+            sample_errors: false,
+        }
+    }
+
+    pub fn with_rules(mut self, rules: &[SamplingRule]) -> Self {
+        self.rules = rules
+            .iter()
+            .map(|rule| (rule.clone(), ratio_sampler(Some(rule.ratio))))
+            .collect();
+        self
+    }
+
+    pub fn with_drop_routes(mut self, routes: &[String]) -> Self {
+        self.drop_routes = routes.to_vec();
+        self
+    }
+
+    pub fn with_sample_errors(mut self, sample_errors: bool) -> Self {
+        self.sample_errors = sample_errors;
+        self
+    }
+
+    fn select(
+        &self,
+        parent_context: Option<&Context>,
+        name: &str,
+        attributes: &OrderMap<Key, Value>,
+    ) -> &Sampler {
+        if parent_context.is_some_and(|cx| cx.has_active_span() && !is_deferred(cx)) {
+            return &PARENT;
+        }
+        if self.sample_errors && is_error(attributes) {
+            return &ALWAYS_ON;
+        }
+        if self
+            .drop_routes
+            .iter()
+            .any(|route| route_matches(route, name))
+        {
+            return &ALWAYS_OFF;
+        }
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(name))
+            .map_or(&self.default, |(_, sampler)| sampler)
+    }
+}
+
+impl ShouldSample for RuleSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        span_kind: &SpanKind,
+        attributes: &OrderMap<Key, Value>,
+        links: &[Link],
+        instrumentation_library: &InstrumentationLibrary,
+    ) -> SamplingResult {
+        self.select(parent_context, name, attributes).should_sample(
+            parent_context,
+            trace_id,
+            name,
+            span_kind,
+            attributes,
+            links,
+            instrumentation_library,
+        )
+    }
+}
+
+fn ratio_sampler(ratio: Option<f64>) -> Sampler {
+    match ratio {
+        Some(ratio) => Sampler::TraceIdRatioBased(ratio),
+        None => Sampler::AlwaysOn,
+    }
+}
+
+/// Whether the parent comes from another service that left the sampling decision to this one.
+///
+/// Local spans inherit the flag, so only remote parents are considered.
+fn is_deferred(cx: &Context) -> bool {
+    let span = cx.span();
+    let span_context = span.span_context();
+    span_context.is_remote()
+        && span_context.trace_flags() & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED
+}
+
+fn is_error(attributes: &OrderMap<Key, Value>) -> bool {
+    attributes.iter().any(|(key, value)| {
+        let key = key.as_str();
+        if ERROR_ATTRIBUTES.contains(&key) {
+            return !matches!(value, Value::Bool(false));
+        }
+        if STATUS_CODE_ATTRIBUTES.contains(&key) {
+            return matches!(value, Value::I64(code) if *code >= 500);
+        }
+        false
+    })
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use opentelemetry::sdk::trace::ShouldSample;
+    use opentelemetry::trace::{
+        OrderMap, SamplingDecision, SpanContext, SpanId, SpanKind, TraceContextExt, TraceFlags,
+        TraceId, TraceState,
+    };
+    use opentelemetry::{Context, InstrumentationLibrary, Key, Value};
+
+    use super::{RuleSampler, SamplingRule};
+    use crate::propagation::TRACE_FLAG_DEFERRED;
+
+    fn decision(
+        sampler: &RuleSampler,
+        parent_sampled: Option<bool>,
+        name: &str,
+        attributes: &[(&'static str, Value)],
+    ) -> SamplingDecision {
+        let parent_flags = parent_sampled.map(|sampled| {
+            if sampled {
+                TraceFlags::SAMPLED
+            } else {
+                TraceFlags::default()
+            }
+        });
+        decision_with_parent_flags(sampler, parent_flags, name, attributes)
+    }
+
+    fn decision_with_parent_flags(
+        sampler: &RuleSampler,
+        parent_flags: Option<TraceFlags>,
+        name: &str,
+        attributes: &[(&'static str, Value)],
+    ) -> SamplingDecision {
+        let parent_context = parent_flags.map(|flags| {
+            Context::new().with_remote_span_context(SpanContext::new(
+                TraceId::from_bytes([1; 16]),
+                SpanId::from_bytes([1; 8]),
+                flags,
+                true,
+                TraceState::default(),
+            ))
+        });
+        let attributes: OrderMap<Key, Value> = attributes
+            .iter()
+            .map(|(key, value)| (Key::from_static_str(key), value.clone()))
+            .collect();
+
+        sampler
+            .should_sample(
+                parent_context.as_ref(),
+                TraceId::from_bytes([1; 16]),
+                name,
+                &SpanKind::Server,
+                &attributes,
+                &[],
+                &InstrumentationLibrary::default(),
+            )
+            .decision
+    }
+
+    #[test]
+    fn follow_parent_decision() {
+        let sampler = RuleSampler::new(Some(0.0));
+        assert_eq!(
+            decision(&sampler, Some(true), "GET /users", &[]),
+            SamplingDecision::RecordAndSample
+        );
+
+        let sampler = RuleSampler::new(None);
+        assert_eq!(
+            decision(&sampler, Some(false), "GET /users", &[]),
+            SamplingDecision::Drop
+        );
+    }
+
+    #[test]
+    fn decide_when_parent_deferred() {
+        let sampler = RuleSampler::new(Some(1.0));
+        assert_eq!(
+            decision_with_parent_flags(&sampler, Some(TRACE_FLAG_DEFERRED), "GET /users", &[]),
+            SamplingDecision::RecordAndSample
+        );
+
+        let sampler = RuleSampler::new(Some(0.0));
+        assert_eq!(
+            decision_with_parent_flags(&sampler, Some(TRACE_FLAG_DEFERRED), "GET /users", &[]),
+            SamplingDecision::Drop
+        );
+    }
+
+    #[test]
+    fn sample_root_spans_by_route() {
+        let rules = [
+            "GET /users/:id=0".parse::<SamplingRule>().unwrap(),
+            "/orders=1.0".parse().unwrap(),
+        ];
+        let sampler = RuleSampler::new(Some(0.0))
+            .with_rules(&rules)
+            .with_drop_routes(&["/health".to_string()]);
+
+        assert_eq!(
+            decision(&sampler, None, "GET /orders", &[]),
+            SamplingDecision::RecordAndSample
+        );
+        assert_eq!(
+            decision(&sampler, None, "GET /users/:id", &[]),
+            SamplingDecision::Drop
+        );
+        assert_eq!(
+            decision(&sampler, None, "GET /", &[]),
+            SamplingDecision::Drop
+        );
+
+        let sampler = RuleSampler::new(None).with_drop_routes(&["/health".to_string()]);
+        assert_eq!(
+            decision(&sampler, None, "GET /health", &[]),
+            SamplingDecision::Drop
+        );
+        assert_eq!(
+            decision(&sampler, None, "GET /healthy", &[]),
+            SamplingDecision::RecordAndSample
+        );
+    }
+
+    #[test]
+    fn sample_errors() {
+        let sampler = RuleSampler::new(Some(0.0)).with_sample_errors(true);
+
+        assert_eq!(
+            decision(&sampler, None, "GET /", &[("status_code", Value::I64(503))]),
+            SamplingDecision::RecordAndSample
+        );
+        // the parent decision is kept, so traces are never partial
+        assert_eq!(
+            decision(
+                &sampler,
+                Some(false),
+                "GET /",
+                &[("status_code", Value::I64(503))]
+            ),
+            SamplingDecision::Drop
+        );
+        assert_eq!(
+            decision(
+                &sampler,
+                None,
+                "query",
+                &[("error", Value::from("timeout"))]
+            ),
+            SamplingDecision::RecordAndSample
+        );
+        assert_eq!(
+            decision(&sampler, None, "GET /", &[("status_code", Value::I64(404))]),
+            SamplingDecision::Drop
+        );
+        assert_eq!(
+            decision(&sampler, None, "query", &[("error", Value::Bool(false))]),
+            SamplingDecision::Drop
+        );
+    }
+
+    #[test]
+    fn parse_sampling_rule() {
+        assert_eq!(
+            "GET /users/:id = 0.25".parse::<SamplingRule>(),
+            Ok(SamplingRule {
+                route: "GET /users/:id".to_string(),
+                ratio: 0.25
+            })
+        );
+        assert!("/users".parse::<SamplingRule>().is_err());
+        assert!("/users=all".parse::<SamplingRule>().is_err());
+    }
+}
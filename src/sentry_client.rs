@@ -0,0 +1,366 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+use eyre::WrapErr;
+use sentry::protocol::{Breadcrumb, Context, Event, Exception, Value};
+use sentry::{ClientInitGuard, ClientOptions};
+use sentry_tracing::{EventFilter, EventMapping, SentryLayer};
+use tracing::field::{Field, Visit};
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::build_info;
+use crate::error_reporting::{ErrorReport, ERROR_REPORT_FIELD, PANIC_KIND};
+use crate::lang::sensitive::scrub;
+use crate::{current_trace_link, Parser, Result, Sensitive};
+
+const TRACE_LINK_KEY: &str = "trace_link";
+
+// -----------------------------------------------------------------------------
+// Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Parser)]
+pub struct SentryConfig {
+    /// Sentry project DSN. Errors are not reported to Sentry when not set.
+    #[clap(long = "sentry-dsn", env = "SENTRY_DSN")]
+    pub sentry_dsn: Option<Sensitive<String>>,
+
+    /// Environment of reported errors. Defaults to `ENVIRONMENT`.
+    #[clap(long = "sentry-environment", env = "SENTRY_ENVIRONMENT")]
+    pub sentry_environment: Option<String>,
+
+    /// Release of reported errors. Defaults to the git commit of the registered build information.
+    #[clap(long = "sentry-release", env = "SENTRY_RELEASE")]
+    pub sentry_release: Option<String>,
+
+    /// Ratio of error events sent to Sentry, between `0.0` and `1.0`.
+    #[clap(
+        long = "sentry-sample-rate",
+        env = "SENTRY_SAMPLE_RATE",
+        default_value = "1.0"
+    )]
+    pub sentry_sample_rate: f32,
+
+    /// Ratio of transactions sent to Sentry for performance monitoring, between `0.0` and `1.0`.
+    #[clap(
+        long = "sentry-traces-sample-rate",
+        env = "SENTRY_TRACES_SAMPLE_RATE",
+        default_value = "0.0"
+    )]
+    pub sentry_traces_sample_rate: f32,
+}
+
+impl SentryConfig {
+    /// Initializes the Sentry client when a DSN is configured.
+    ///
+    /// The link to the trace of the current span is added to events when `attach_trace_link` is set.
+    ///
+    /// The returned guard flushes pending events when dropped, so it must be kept alive until shutdown.
+    pub(crate) fn init(
+        &self,
+        environment: Option<&str>,
+        attach_trace_link: bool,
+    ) -> Result<Option<SentryGuard>> {
+        let Some(dsn) = &self.sentry_dsn else {
+            return Ok(None);
+        };
+
+        let options = ClientOptions {
+            dsn: Some(dsn.parse().wrap_err("Invalid Sentry DSN")?),
+            environment: self
+                .sentry_environment
+                .as_deref()
+                .or(environment)
+                .map(|environment| Cow::Owned(environment.to_string())),
+            release: self.release().map(Cow::Owned),
+            sample_rate: self.sentry_sample_rate,
+            traces_sample_rate: self.sentry_traces_sample_rate,
+            before_send: Some(Arc::new(move |mut event| {
+                if attach_trace_link {
+                    add_trace_link(&mut event);
+                }
+                Some(scrub_event(event))
+            })),
+            before_breadcrumb: Some(Arc::new(|mut breadcrumb| {
+                scrub_breadcrumb(&mut breadcrumb);
+                Some(breadcrumb)
+            })),
+            ..Default::default()
+        };
+        Ok(Some(SentryGuard(sentry::init(options))))
+    }
+
+    fn release(&self) -> Option<String> {
+        self.sentry_release.clone().or_else(|| {
+            build_info::current().map(|build_info| build_info.git.commit_hash.to_string())
+        })
+    }
+}
+
+/// Keeps the Sentry client alive, flushing pending events when dropped.
+pub(crate) struct SentryGuard(#[allow(dead_code)] ClientInitGuard);
+
+impl Debug for SentryGuard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SentryGuard").finish()
+    }
+}
+
+/// Adds the link to the trace of the current span as a tag, so events can be searched by it.
+fn add_trace_link(event: &mut Event<'static>) {
+    if let Some(link) = current_trace_link() {
+        event
+            .extra
+            .insert(TRACE_LINK_KEY.to_string(), Value::from(link.clone()));
+        event.tags.insert(TRACE_LINK_KEY.to_string(), link);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Layer
+// -----------------------------------------------------------------------------
+
+/// Sentry layer reporting events recorded by [`report_error`](crate::report_error) as exceptions
+/// with their whole cause chain, span trace and rendered report.
+pub(crate) fn sentry_layer<S>() -> SentryLayer<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    sentry_tracing::layer().event_mapper(|event, ctx| {
+        match sentry_tracing::default_event_filter(event.metadata()) {
+            EventFilter::Ignore => EventMapping::Ignore,
+            EventFilter::Breadcrumb => {
+                EventMapping::Breadcrumb(sentry_tracing::breadcrumb_from_event(event))
+            }
+            EventFilter::Event | EventFilter::Exception => {
+                let mut visitor = ErrorReportVisitor::default();
+                event.record(&mut visitor);
+
+                let mut sentry_event = sentry_tracing::exception_from_event(event, ctx);
+                match visitor.report {
+                    // already reported, with its stack trace, by the panic integration of Sentry
+                    Some(report) if report.kind == PANIC_KIND => EventMapping::Ignore,
+                    Some(report) => {
+                        add_error_report(&mut sentry_event, report);
+                        EventMapping::Event(sentry_event)
+                    }
+                    None => EventMapping::Event(sentry_event),
+                }
+            }
+        }
+    })
+}
+
+fn add_error_report(event: &mut Event<'static>, report: ErrorReport) {
+    // Sentry expects the root cause first and shows the last exception as the main one
+    let root_cause = report.chain.len().saturating_sub(1);
+    let exceptions: Vec<Exception> = report
+        .chain
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(index, message)| Exception {
+            ty: if index == root_cause {
+                report.kind.clone()
+            } else {
+                "Error".to_string()
+            },
+            value: Some(message.clone()),
+            ..Default::default()
+        })
+        .collect();
+    event.exception = exceptions.into();
+
+    // the report is attached below in a structured way, not as a raw tracing field
+    if let Some(Context::Other(fields)) = event.contexts.get_mut("Rust Tracing Tags") {
+        fields.remove(ERROR_REPORT_FIELD);
+    }
+
+    if !report.span_trace.is_empty() {
+        let frames: BTreeMap<String, Value> = report
+            .span_trace
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let frame = serde_json::to_value(frame).unwrap_or_default();
+                (format!("{:02}", index), frame)
+            })
+            .collect();
+        event
+            .contexts
+            .insert("Span Trace".to_string(), Context::Other(frames));
+    }
+    if let Some(backtrace) = report.backtrace {
+        event
+            .extra
+            .insert("backtrace".to_string(), Value::from(backtrace));
+    }
+    if let Some(rendered) = report.report {
+        event
+            .extra
+            .insert("report".to_string(), Value::from(rendered));
+    }
+}
+
+/// Extracts the [`ErrorReport`] recorded as JSON by [`report_error`](crate::report_error), or
+/// built from the first `dyn Error` field.
+#[derive(Default)]
+struct ErrorReportVisitor {
+    report: Option<ErrorReport>,
+}
+
+impl Visit for ErrorReportVisitor {
+    fn record_error(&mut self, _field: &Field, value: &(dyn std::error::Error + 'static)) {
+        if self.report.is_none() {
+            self.report = Some(ErrorReport::from_error(value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == ERROR_REPORT_FIELD {
+            self.report = serde_json::from_str(&format!("{:?}", value)).ok();
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Scrubbing
+// -----------------------------------------------------------------------------
+
+/// Masks values parsed as `Sensitive` in every free-form text of the event.
+fn scrub_event(mut event: Event<'static>) -> Event<'static> {
+    scrub_option(&mut event.message);
+    if let Some(logentry) = &mut event.logentry {
+        scrub_string(&mut logentry.message);
+        logentry.params.iter_mut().for_each(scrub_value);
+    }
+    for exception in &mut event.exception.values {
+        scrub_option(&mut exception.value);
+    }
+    for breadcrumb in &mut event.breadcrumbs.values {
+        scrub_breadcrumb(breadcrumb);
+    }
+    if let Some(request) = &mut event.request {
+        scrub_option(&mut request.data);
+        scrub_option(&mut request.query_string);
+        scrub_option(&mut request.cookies);
+        request.headers.values_mut().for_each(scrub_string);
+    }
+    event.tags.values_mut().for_each(scrub_string);
+    event.extra.values_mut().for_each(scrub_value);
+    for context in event.contexts.values_mut() {
+        if let Context::Other(values) = context {
+            values.values_mut().for_each(scrub_value);
+        }
+    }
+    event
+}
+
+fn scrub_breadcrumb(breadcrumb: &mut Breadcrumb) {
+    scrub_option(&mut breadcrumb.message);
+    breadcrumb.data.values_mut().for_each(scrub_value);
+}
+
+fn scrub_value(value: &mut Value) {
+    match value {
+        Value::String(text) => scrub_string(text),
+        Value::Array(values) => values.iter_mut().for_each(scrub_value),
+        Value::Object(values) => values.values_mut().for_each(scrub_value),
+        _ => {}
+    }
+}
+
+fn scrub_option(text: &mut Option<String>) {
+    if let Some(text) = text {
+        scrub_string(text);
+    }
+}
+
+fn scrub_string(text: &mut String) {
+    if let Cow::Owned(scrubbed) = scrub(text) {
+        *text = scrubbed;
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use sentry::protocol::{Breadcrumb, Event, Exception, Value};
+
+    use crate::lang::sensitive::MASK;
+    use crate::{Parser, Sensitive};
+
+    use crate::error_reporting::ErrorReport;
+
+    use super::{add_error_report, scrub_event, SentryConfig};
+
+    #[test]
+    fn scrub_sensitive_values_from_events() {
+        let _: Sensitive<String> = "sentry-scrub-secret".parse().unwrap();
+
+        let mut event = Event {
+            message: Some("token sentry-scrub-secret rejected".to_string()),
+            ..Default::default()
+        };
+        event.exception.values.push(Exception {
+            value: Some("sentry-scrub-secret".to_string()),
+            ..Default::default()
+        });
+        event.breadcrumbs.values.push(Breadcrumb {
+            data: [(
+                "config".to_string(),
+                Value::from(vec!["sentry-scrub-secret"]),
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        });
+
+        let event = scrub_event(event);
+
+        assert_eq!(event.message, Some(format!("token {} rejected", MASK)));
+        assert_eq!(event.exception.values[0].value, Some(MASK.to_string()));
+        assert_eq!(
+            event.breadcrumbs.values[0].data["config"],
+            Value::from(vec![MASK])
+        );
+    }
+
+    #[test]
+    fn error_report_as_exceptions() {
+        let mut event = Event::default();
+        add_error_report(
+            &mut event,
+            ErrorReport {
+                kind: "ParseIntError".to_string(),
+                chain: vec!["Invalid quantity".to_string(), "invalid digit".to_string()],
+                report: Some("Error: Invalid quantity".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let exceptions = &event.exception.values;
+        assert_eq!(exceptions[0].ty, "ParseIntError");
+        assert_eq!(exceptions[0].value.as_deref(), Some("invalid digit"));
+        assert_eq!(exceptions[1].ty, "Error");
+        assert_eq!(exceptions[1].value.as_deref(), Some("Invalid quantity"));
+        assert_eq!(
+            event.extra["report"],
+            Value::from("Error: Invalid quantity")
+        );
+    }
+
+    #[test]
+    fn sentry_is_not_initialized_without_dsn() {
+        let config = SentryConfig::parse_from(["test", "--sentry-release", "1.0.0"]);
+
+        assert!(config.init(Some("test"), true).unwrap().is_none());
+        assert_eq!(config.release().as_deref(), Some("1.0.0"));
+    }
+}
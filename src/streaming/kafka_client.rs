@@ -1,22 +1,32 @@
 use std::{
+    collections::HashMap,
     fmt::{Debug, Formatter},
     time::Duration,
 };
 
 use base64::{engine::general_purpose, Engine as _};
 use eyre::WrapErr;
+use opentelemetry::global;
 use rdkafka::{
     message::{Header, OwnedHeaders},
     producer::{FutureProducer, FutureRecord, Producer},
     ClientConfig,
 };
 
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
 use super::{KafkaConfig, Message, StreamingClient};
 
-use crate::{Result, Sensitive};
+use crate::{
+    async_trait, current_request_id, request_id_header, Feature, Features, Result, Sensitive,
+};
 
 const NO_RETRY: Duration = Duration::from_secs(0);
 
+/// Maximum time pending messages have to be delivered on shutdown.
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 pub struct KafkaClient {
     producer: FutureProducer,
@@ -58,6 +68,39 @@ impl KafkaClient {
 
         Ok(client)
     }
+
+    pub async fn health_check(&self) -> Result<()> {
+        self.producer
+            .client()
+            .fetch_metadata(Some(&self.health_check_topic), Duration::from_millis(500))
+            .wrap_err("Failed to check Kafka health")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Feature for KafkaClient {
+    type Config = KafkaConfig;
+    const NAME: &'static str = "kafka";
+    const TITLE: &'static str = "Kafka";
+    const CARGO_FEATURE: Option<&'static str> = Some("streaming");
+
+    async fn init(_service_name: &str, config: &KafkaConfig, _: &Features) -> Result<Self> {
+        Self::new(config).await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        KafkaClient::health_check(self).await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        // flushing blocks until the messages are delivered or the timeout expires
+        let producer = self.producer.clone();
+        tokio::task::spawn_blocking(move || producer.flush(SHUTDOWN_FLUSH_TIMEOUT))
+            .await?
+            .wrap_err("Failed to deliver pending Kafka messages")?;
+        Ok(())
+    }
 }
 
 fn pem_string_from_base64(base64: &Sensitive<String>) -> Result<Sensitive<String>> {
@@ -67,10 +110,22 @@ fn pem_string_from_base64(base64: &Sensitive<String>) -> Result<Sensitive<String
     Ok(Sensitive::from(pem_text.to_string()))
 }
 
-#[crate::async_trait]
+#[async_trait]
 impl StreamingClient for KafkaClient {
     /// Publishes a pre-defined Kafka message to the broker.
-    async fn publish(&self, message: Message) -> Result<()> {
+    async fn publish(&self, mut message: Message) -> Result<()> {
+        // propagate trace context, baggage and request id, keeping headers set by the caller
+        let mut context_headers = HashMap::new();
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&Span::current().context(), &mut context_headers)
+        });
+        if let Some(request_id) = current_request_id() {
+            context_headers.insert(request_id_header().to_string(), request_id);
+        }
+        for (key, value) in context_headers {
+            message.headers.entry(key).or_insert(value);
+        }
+
         // convert headers
         let mut kafka_headers = OwnedHeaders::new_with_capacity(message.headers.len());
         for (key, value) in message.headers.into_iter() {
@@ -96,11 +151,7 @@ impl StreamingClient for KafkaClient {
     }
 
     async fn health_check(&self) -> Result<()> {
-        self.producer
-            .client()
-            .fetch_metadata(Some(&self.health_check_topic), Duration::from_millis(500))
-            .wrap_err("Failed to check Kafka health")?;
-        Ok(())
+        KafkaClient::health_check(self).await
     }
 }
 
@@ -2,18 +2,24 @@ use crate::{Parser, Sensitive};
 
 #[derive(Debug, Clone, Parser)]
 pub struct KafkaConfig {
+    /// Comma-separated addresses of the Kafka brokers.
     #[clap(long = "kafka-url", env = "KAFKA_URL")]
     pub kafka_url: String,
 
-    #[clap(long = "kafka-url", env = "KAFKA_HEALTH_CHECK_TOPIC")]
+    /// Topic whose metadata is fetched to check the connection.
+    #[clap(long = "kafka-health-check-topic", env = "KAFKA_HEALTH_CHECK_TOPIC")]
     pub kafka_health_check_topic: String,
 
+    /// Base64 encoded PEM private key of the client. SSL is used when the key, certificate and CA
+    /// are set.
     #[clap(long = "kafka-key", env = "KAFKA_KEY")]
     pub kafka_key: Option<Sensitive<String>>,
 
+    /// Base64 encoded PEM certificate of the client.
     #[clap(long = "kafka-cert", env = "KAFKA_CERT")]
     pub kafka_cert: Option<Sensitive<String>>,
 
+    /// Base64 encoded PEM certificate of the CA of the brokers.
     #[clap(long = "kafka-ca", env = "KAFKA_CA")]
     pub kafka_ca: Option<Sensitive<String>>,
 }
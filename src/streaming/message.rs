@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use opentelemetry::{global, Context};
+
 use crate::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -9,3 +11,13 @@ pub struct Message {
     pub payload: String,
     pub headers: HashMap<String, String>,
 }
+
+impl Message {
+    /// Extracts the trace context and baggage propagated in the message headers by the publisher.
+    ///
+    /// Consumers can use it as the parent of the span processing the message with
+    /// `OpenTelemetrySpanExt::set_parent`.
+    pub fn remote_context(&self) -> Context {
+        global::get_text_map_propagator(|propagator| propagator.extract(&self.headers))
+    }
+}
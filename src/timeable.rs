@@ -4,13 +4,15 @@ use tokio::time::Instant;
 
 use once_cell::sync::{Lazy, OnceCell};
 
+use crate::resource::metric_name;
+
 static METRIC_SUFFIX: Lazy<String> = Lazy::new(|| "task_duration_ms".to_string());
 
 static METRIC_NAME: OnceCell<String> = OnceCell::new();
 
 /// Inits the `timeable` module, creating and describing the metrics that will be tracked.
 pub fn init(service_name: &str) {
-    let metric_name = format!("{}_{}", service_name, METRIC_SUFFIX.as_str());
+    let metric_name = metric_name(service_name, METRIC_SUFFIX.as_str());
 
     METRIC_NAME.get_or_init(|| metric_name.clone());
     describe_histogram!(metric_name, "Task execution duration in milliseconds.");
@@ -1,11 +1,31 @@
-use std::{borrow::Cow, fmt::Debug, str::FromStr, thread, time::SystemTime};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::HashSet,
+    fmt::{Debug, Display},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
 
-use axum::extract::{MatchedPath, OriginalUri};
+use axum::{
+    body::HttpBody,
+    extract::{MatchedPath, OriginalUri},
+};
 use chrono::{DateTime, SecondsFormat, Utc};
-use http::{header::HeaderName, HeaderMap, HeaderValue, Method, Request};
-use opentelemetry::sdk::propagation::TraceContextPropagator;
-use opentelemetry::sdk::trace::{self, Sampler};
-use opentelemetry::trace::TraceContextExt;
+use eyre::WrapErr;
+use http::{
+    header::{HeaderName, CONTENT_LENGTH},
+    HeaderMap, HeaderValue, Method, Request, Response, Uri,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use opentelemetry::baggage::BaggageExt;
+use opentelemetry::sdk::trace;
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
 use opentelemetry::{
     global,
     propagation::{Extractor, Injector},
@@ -16,27 +36,50 @@ use serde::Serialize;
 use serde_json::Value;
 use tower_http::{
     request_id::{MakeRequestId, RequestId},
-    trace::MakeSpan,
+    trace::{MakeSpan, OnFailure, OnResponse},
 };
-use tracing::{Event, Id, Level, Span, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing::{span, Event, Level, Span, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_error::ErrorLayer;
 use tracing_opentelemetry::{OpenTelemetrySpanExt, OtelData};
-use tracing_serde::fields::AsMap;
 use tracing_subscriber::{
+    field::RecordFields,
     fmt::{
-        format::{DefaultFields, Writer},
-        {FmtContext, FormatEvent, Layer},
+        format::Writer,
+        MakeWriter, {FmtContext, FormatEvent, FormatFields, Layer},
     },
-    layer::SubscriberExt,
+    layer::{Context as LayerContext, SubscriberExt},
     registry::{LookupSpan, SpanRef},
+    reload,
     util::SubscriberInitExt,
     Layer as TracingLayer, {EnvFilter, Registry},
 };
 use tracing_tree::HierarchicalLayer;
 use uuid::Uuid;
 
-use crate::{async_trait, EnvironmentConfig, Feature, Parser, Result};
+use crate::error_reporting::{ErrorReport, ERROR_REPORT_FIELD};
+use crate::lang::sensitive::MASK;
+use crate::log_output::{LogOutputConfig, LogWriter};
+use crate::propagation::{composite_propagator, TracePropagator};
+use crate::resource::ResourceConfig;
+use crate::sampling::{RuleSampler, SamplingRule};
+use crate::trace_link::{root_trace_link, TraceLinkConfig, TraceLinkLayer, TraceLinks};
+#[cfg(feature = "sentry")]
+use crate::{
+    sentry_client::{sentry_layer, SentryGuard},
+    SentryConfig,
+};
+use crate::{EnvironmentConfig, Parser, Result};
+
+static REQUEST_ID_HEADER: OnceCell<HeaderName> = OnceCell::new();
 
-const NOOP_SPAN_ID: &str = "00000000000000000000000000000000";
+/// Tracer of the OpenTelemetry exporter, holding a weak reference to its provider so it can be
+/// flushed without preventing its shutdown.
+static TRACER: OnceCell<opentelemetry::sdk::trace::Tracer> = OnceCell::new();
+
+/// Guard of the log writer worker, taken to write the buffered log lines on shutdown.
+static LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
 
 // -----------------------------------------------------------------------------
 // Supported Formats
@@ -71,17 +114,36 @@ pub enum TracingFormat {
     JsonPretty,
 }
 
+/// Field layout of JSON formatted events, for log pipelines that expect specific field names.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JsonSchema {
+    /// Balthazar own layout, with `fields` and `context` objects and span names.
+    #[default]
+    Default,
+
+    /// Elastic Common Schema, with `@timestamp`, `log.level`, `trace.id` and `span.id`.
+    Ecs,
+
+    /// Google Cloud Logging structured logging, with `severity` and `logging.googleapis.com/trace`.
+    Gcp,
+
+    /// Datadog, with `status` and decimal `dd.trace_id` and `dd.span_id` for trace correlation.
+    Datadog,
+}
+
 // -----------------------------------------------------------------------------
 // Config
 // -----------------------------------------------------------------------------
 #[derive(Debug, Clone, Parser)]
 pub struct TracingConfig {
+    /// Disables exporting metrics and traces to the OpenTelemetry collector.
     #[clap(
         long = "tracing-disable-opentelemetry",
         env = "TRACING_DISABLE_OPENTELEMETRY"
     )]
     pub disable_opentelemetry: bool,
 
+    /// Address of the OpenTelemetry collector.
     #[clap(
         long = "tracing-opentelemetry-endpoint",
         env = "TRACING_OPENTELEMETRY_ENDPOINT",
@@ -89,6 +151,7 @@ pub struct TracingConfig {
     )]
     pub opentelemetry_endpoint: String,
 
+    /// Filter directives of the formatter layer, using the `RUST_LOG` syntax (e.g. `info,my_crate=debug`).
     #[clap(
         long = "tracing-log-level",
         env = "TRACING_LOG_LEVEL",
@@ -96,6 +159,17 @@ pub struct TracingConfig {
     )]
     pub log_level: String,
 
+    /// Filter directives of the OpenTelemetry layer. Defaults to `TRACING_LOG_LEVEL`.
+    #[clap(long = "tracing-otel-level", env = "TRACING_OTEL_LEVEL")]
+    pub otel_level: Option<String>,
+
+    /// Filter directives of the Sentry layer. Defaults to `TRACING_LOG_LEVEL`.
+    #[cfg(feature = "sentry")]
+    #[clap(long = "tracing-sentry-level", env = "TRACING_SENTRY_LEVEL")]
+    pub sentry_level: Option<String>,
+
+    /// Format of events: `json` or `json-pretty` for JSON, `text` or `text-pretty` for formatted
+    /// text, `hierarchical` for indented spans, or `none` to not write them.
     #[clap(
         value_enum,
         long = "tracing-format",
@@ -104,88 +178,203 @@ pub struct TracingConfig {
     )]
     pub format: TracingFormat,
 
+    /// Field layout of JSON formatted events when format is `json` or `json-pretty`.
+    #[clap(
+        value_enum,
+        long = "tracing-json-schema",
+        env = "TRACING_JSON_SCHEMA",
+        default_value = "default"
+    )]
+    pub json_schema: JsonSchema,
+
+    /// Google Cloud project used to build trace names when the JSON schema is `gcp`.
+    #[clap(long = "tracing-gcp-project-id", env = "TRACING_GCP_PROJECT_ID")]
+    pub gcp_project_id: Option<String>,
+
+    /// Ratio of root spans sampled. Spans with a parent, local or from another service, follow the
+    /// parent decision.
     #[clap(long = "tracing-sample-rate", env = "TRACING_SAMPLE_RATE")]
     pub sample_rate: Option<f64>,
 
-    #[cfg(feature = "sentry")]
+    /// Comma-separated `<route>=<ratio>` rules overriding the sample rate of matching root spans,
+    /// like `GET /users/:id=0.1` or `/orders=1`.
+    #[clap(
+        long = "tracing-sample-routes",
+        env = "TRACING_SAMPLE_ROUTES",
+        value_delimiter = ','
+    )]
+    pub sample_routes: Vec<SamplingRule>,
+
+    /// Comma-separated routes whose root spans are never sampled.
+    #[clap(
+        long = "tracing-sample-drop-routes",
+        env = "TRACING_SAMPLE_DROP_ROUTES",
+        value_delimiter = ',',
+        default_value = "/health,/healthz,/livez,/readyz,/metrics"
+    )]
+    pub sample_drop_routes: Vec<String>,
+
+    /// Samples root spans created with an error attribute, like `error` or a server error
+    /// `http.status_code`, whatever their sample rate. Errors recorded after the span is created,
+    /// like the status of HTTP responses, are not taken into account.
+    #[clap(
+        long = "tracing-sample-errors",
+        env = "TRACING_SAMPLE_ERRORS",
+        default_value_t = true,
+        action = clap::ArgAction::Set
+    )]
+    pub sample_errors: bool,
+
+    /// Header carrying the request ID in incoming and outgoing requests and Kafka messages.
+    #[clap(
+        long = "tracing-request-id-header",
+        env = "TRACING_REQUEST_ID_HEADER",
+        default_value = "x-request-id"
+    )]
+    pub request_id_header: String,
+
+    /// Comma-separated baggage keys added to the `context` of JSON formatted events.
+    #[clap(
+        long = "tracing-baggage-log-keys",
+        env = "TRACING_BAGGAGE_LOG_KEYS",
+        value_delimiter = ','
+    )]
+    pub baggage_log_keys: Vec<String>,
+
+    /// Comma-separated formats used to propagate the trace context in HTTP requests and Kafka
+    /// messages: `tracecontext`, `baggage`, `b3`, `b3multi` or `jaeger`.
+    #[clap(
+        value_enum,
+        long = "tracing-propagators",
+        env = "TRACING_PROPAGATORS",
+        value_delimiter = ',',
+        default_value = "tracecontext,baggage"
+    )]
+    pub propagators: Vec<TracePropagator>,
+
     #[clap(flatten)]
-    pub honeycomb: HoneycombConfig,
-}
+    pub output: LogOutputConfig,
 
-// -----------------------------------------------------------------------------
-// Honeycomb Config
-// -----------------------------------------------------------------------------
-#[derive(Debug, Clone, Parser)]
-pub struct HoneycombConfig {
-    #[clap(env = "HONEYCOMB_TEAM", default_value = "infinitepay---issuing")]
-    pub honeycomb_team: String,
+    #[clap(flatten)]
+    pub resource: ResourceConfig,
+
+    #[cfg(feature = "sentry")]
+    #[clap(flatten)]
+    pub sentry: SentryConfig,
 
-    #[clap(env = "ENVIRONMENT", default_value = "staging")]
-    pub honeycomb_environment: String,
+    #[clap(flatten)]
+    pub trace_link: TraceLinkConfig,
 }
 
 // -----------------------------------------------------------------------------
 // Service
 // -----------------------------------------------------------------------------
-#[derive(clap::Parser, Debug)]
-pub struct Tracing;
+#[derive(Debug)]
+pub struct Tracing {
+    /// Filter of the formatter layer, when events are formatted.
+    log_filter: Option<LogFilter>,
+
+    /// Flushes pending Sentry events when tracing is dropped.
+    #[cfg(feature = "sentry")]
+    _sentry_guard: Option<SentryGuard>,
+}
 
-#[async_trait]
-impl Feature for Tracing {
-    async fn init(service_name: &str, config: &EnvironmentConfig) -> Result<Self> {
-        std::env::set_var("RUST_LOG", &config.tracing.log_level);
+impl Tracing {
+    /// Installs the tracing subscriber, with the layers enabled by the configuration.
+    pub async fn init(service_name: &str, config: &EnvironmentConfig) -> Result<Self> {
+        let request_id_header = HeaderName::from_str(&config.tracing.request_id_header)
+            .wrap_err("Invalid request ID header name")?;
+        let _ = REQUEST_ID_HEADER.set(request_id_header);
 
         // OPENTELEMETRY LAYER
         let telemetry_layer = if config.tracing.disable_opentelemetry {
             None
         } else {
-            let sampler = match config.tracing.sample_rate {
-                Some(v) => Sampler::TraceIdRatioBased(v),
-                None => Sampler::AlwaysOn,
-            };
+            let sampler = RuleSampler::new(config.tracing.sample_rate)
+                .with_rules(&config.tracing.sample_routes)
+                .with_drop_routes(&config.tracing.sample_drop_routes)
+                .with_sample_errors(config.tracing.sample_errors);
 
             let tracer = opentelemetry_jaeger::new_collector_pipeline()
                 .with_endpoint(&config.tracing.opentelemetry_endpoint)
                 .with_service_name(service_name)
-                .with_trace_config(trace::config().with_sampler(sampler))
+                .with_trace_config(
+                    trace::config()
+                        .with_sampler(sampler)
+                        .with_resource(config.tracing.resource.resource()),
+                )
                 .with_reqwest()
                 .install_batch(opentelemetry::runtime::Tokio)?;
+            let _ = TRACER.set(tracer.clone());
 
+            let directives = config
+                .tracing
+                .otel_level
+                .as_deref()
+                .unwrap_or(&config.tracing.log_level);
             Some(
                 tracing_opentelemetry::layer()
                     .with_tracked_inactivity(false)
-                    .with_tracer(tracer),
+                    .with_tracer(tracer)
+                    .with_filter(layer_filter("OpenTelemetry", directives)?),
             )
         };
 
+        // TRACE LINK LAYER
+        let trace_links = TraceLinks::new(
+            service_name,
+            config.tracing.resource.environment.as_deref(),
+            &config.tracing.trace_link,
+        );
+        let trace_link_layer = trace_links.clone().map(TraceLinkLayer::new);
+        let trace_link_logs = trace_links.is_some() && config.tracing.trace_link.trace_link_logs;
+
         // SENTRY LAYER
         #[cfg(feature = "sentry")]
-        let honeycomb_layer = Some(HoneycombTraceOnSentryScope::new(
-            config.tracing.honeycomb.honeycomb_team.clone(),
-            service_name.to_string(),
-            config.tracing.honeycomb.honeycomb_environment.clone(),
-        ));
-        #[cfg(not(feature = "sentry"))]
-        let honeycomb_layer: Option<HierarchicalLayer> = None; // generic type here does not matter because it will always be None
-
+        let sentry_guard = config.tracing.sentry.init(
+            config.tracing.resource.environment.as_deref(),
+            trace_links.is_some() && config.tracing.trace_link.trace_link_error_reports,
+        )?;
         #[cfg(feature = "sentry")]
-        let sentry_layer = Some(sentry_tracing::layer());
+        let sentry_layer = {
+            let directives = config
+                .tracing
+                .sentry_level
+                .as_deref()
+                .unwrap_or(&config.tracing.log_level);
+            Some(sentry_layer().with_filter(layer_filter("Sentry", directives)?))
+        };
         #[cfg(not(feature = "sentry"))]
         let sentry_layer: Option<HierarchicalLayer> = None; // generic type here does not matter because it will always be None
 
         // FORMATTER LAYER
+        let (writer, log_guard) = LogWriter::init(service_name, &config.tracing.output)?;
+        let ansi = !config.core.no_color && config.tracing.output.supports_ansi();
+
         // fixed by https://github.com/tokio-rs/tracing/issues/575#issuecomment-1219566115
         let formatter_layer = match config.tracing.format {
             TracingFormat::None => None,
             TracingFormat::Json => Some(
-                Layer::default()
-                    .event_format(JsonFormatter::new(service_name.to_string(), false))
-                    .boxed(),
+                json_layer(
+                    JsonFormatter::new(service_name.to_string(), false)
+                        .with_schema(config.tracing.json_schema)
+                        .with_gcp_project_id(config.tracing.gcp_project_id.clone())
+                        .with_baggage_keys(config.tracing.baggage_log_keys.clone())
+                        .with_trace_link(trace_link_logs),
+                    writer,
+                )
+                .boxed(),
             ),
             TracingFormat::JsonPretty => Some(
-                Layer::default()
-                    .event_format(JsonFormatter::new(service_name.to_string(), true))
-                    .boxed(),
+                json_layer(
+                    JsonFormatter::new(service_name.to_string(), true)
+                        .with_schema(config.tracing.json_schema)
+                        .with_gcp_project_id(config.tracing.gcp_project_id.clone())
+                        .with_baggage_keys(config.tracing.baggage_log_keys.clone())
+                        .with_trace_link(trace_link_logs),
+                    writer,
+                )
+                .boxed(),
             ),
             TracingFormat::Text => Some(
                 Layer::default()
@@ -194,7 +383,8 @@ impl Feature for Tracing {
                     .with_target(true)
                     .with_file(true)
                     .with_line_number(true)
-                    .with_ansi(!config.core.no_color)
+                    .with_ansi(ansi)
+                    .with_writer(writer)
                     .boxed(),
             ),
             #[allow(deprecated)]
@@ -206,31 +396,86 @@ impl Feature for Tracing {
                     .with_target(true)
                     .with_file(true)
                     .with_line_number(true)
-                    .with_ansi(!config.core.no_color)
+                    .with_ansi(ansi)
+                    .with_writer(writer)
                     .boxed(),
             ),
             TracingFormat::Hierarchical => Some(
                 HierarchicalLayer::new(2)
                     .with_targets(true)
                     .with_bracketed_fields(true)
-                    .with_ansi(!config.core.no_color)
+                    .with_ansi(ansi)
+                    .with_writer(writer)
                     .boxed(),
             ),
         };
 
+        // without a formatter layer, there is no filter to change
+        let (formatter_layer, log_filter) = match formatter_layer {
+            Some(layer) => {
+                let (formatter_filter, log_filter) = LogFilter::new(&config.tracing.log_level)?;
+                (Some(layer.with_filter(formatter_filter)), Some(log_filter))
+            }
+            None => (None, None),
+        };
+
+        // the formatter layer comes first so its reloadable filter is bound to `Registry`
         Registry::default()
-            .with(EnvFilter::from_default_env())
-            .with(telemetry_layer)
             .with(formatter_layer)
-            .with(honeycomb_layer)
+            .with(telemetry_layer)
+            .with(trace_link_layer)
             .with(sentry_layer)
+            .with(ErrorLayer::default())
             .init();
 
         tracing::debug!("started tracer");
 
-        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_text_map_propagator(composite_propagator(&config.tracing.propagators));
+        *LOG_GUARD.lock().unwrap_or_else(|e| e.into_inner()) = Some(log_guard);
+
+        Ok(Self {
+            log_filter,
+            #[cfg(feature = "sentry")]
+            _sentry_guard: sentry_guard,
+        })
+    }
+}
+
+/// Exports the spans buffered by the OpenTelemetry batch exporter, waiting at most `timeout`, and
+/// writes the buffered log lines.
+///
+/// The log writer is stopped, so lines logged afterwards are lost: only call it on shutdown.
+pub(crate) fn flush_telemetry(timeout: Duration) {
+    flush_log_lines();
+
+    let Some(provider) = TRACER.get().and_then(|tracer| tracer.provider()) else {
+        return;
+    };
+
+    // flushed from another thread, because the exporter may need the runtime thread being blocked
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(provider.force_flush());
+    });
+    let _ = receiver.recv_timeout(timeout);
+}
+
+/// Stops the log writer worker once it has written the buffered log lines.
+fn flush_log_lines() {
+    let guard = LOG_GUARD.lock().ok().and_then(|mut guard| guard.take());
+    drop(guard);
+}
+
+/// Parses the filter directives of a single layer, so each layer can be filtered independently.
+fn layer_filter(layer: &str, directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives)
+        .wrap_err_with(|| format!("Invalid {} filter directives: {}", layer, directives))
+}
 
-        Ok(Self)
+impl Tracing {
+    /// Handle to change the log filter directives at runtime, unless `TRACING_FORMAT` is `none`.
+    pub fn log_filter(&self) -> Option<&LogFilter> {
+        self.log_filter.as_ref()
     }
 }
 
@@ -238,31 +483,113 @@ impl Drop for Tracing {
     fn drop(&mut self) {
         tracing::debug!("stopping tracer");
         opentelemetry::global::shutdown_tracer_provider();
+        flush_log_lines();
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Log Filter
+// -----------------------------------------------------------------------------
+/// Changes the filter directives of the formatter layer at runtime, without restarting the service.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default_directives: Arc<str>,
+
+    /// Incremented on every change, so a pending revert does not undo a newer change.
+    generation: Arc<AtomicU64>,
+}
+
+impl LogFilter {
+    pub(crate) fn new(directives: &str) -> Result<(reload::Layer<EnvFilter, Registry>, Self)> {
+        let (layer, handle) = reload::Layer::new(layer_filter("formatter", directives)?);
+        let log_filter = Self {
+            handle,
+            default_directives: directives.into(),
+            generation: Arc::default(),
+        };
+        Ok((layer, log_filter))
+    }
+
+    /// Directives currently applied.
+    pub fn directives(&self) -> Result<String> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .wrap_err("Failed to read log filter")
+    }
+
+    /// Directives configured in `TRACING_LOG_LEVEL`.
+    pub fn default_directives(&self) -> &str {
+        &self.default_directives
+    }
+
+    /// Applies new directives, reverting to the default ones after `ttl` if given.
+    ///
+    /// Reverting spawns a task, so it must be called within a Tokio runtime when `ttl` is given.
+    pub fn set(&self, directives: &str, ttl: Option<Duration>) -> Result<()> {
+        let generation = self.reload(directives)?;
+        tracing::info!(
+            directives,
+            ttl_secs = ttl.map(|ttl| ttl.as_secs()),
+            "changed log filter"
+        );
+
+        if let Some(ttl) = ttl {
+            let log_filter = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                if log_filter.generation.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                if let Err(e) = log_filter.reset() {
+                    tracing::error!(reason = ?e, "failed to revert log filter");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies the default directives again.
+    pub fn reset(&self) -> Result<()> {
+        self.reload(&self.default_directives.clone())?;
+        tracing::info!(directives = %self.default_directives, "reverted log filter");
+        Ok(())
+    }
+
+    fn reload(&self, directives: &str) -> Result<u64> {
+        let filter = layer_filter("formatter", directives)?;
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.handle
+            .reload(filter)
+            .wrap_err("Failed to change log filter")?;
+        Ok(generation)
+    }
+}
+
+impl Debug for LogFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogFilter")
+            .field("default_directives", &self.default_directives)
+            .finish_non_exhaustive()
     }
 }
 
 // -----------------------------------------------------------------------------
 // Json Formatter
 // -----------------------------------------------------------------------------
-const CONTEXT_FIELDS_TO_IGNORE: [&str; 5] = [
-    "code.filepath",
-    "code.lineno",
-    "code.namespace",
-    "thread.id",
-    "thread.name",
-];
-const EVENT_FIELDS_TO_IGNORE: [&str; 6] = [
-    "code.filepath",
-    "code.lineno",
-    "code.namespace",
-    "level",
-    "name",
-    "target",
-];
+/// Context field holding the link to the trace in the configured tracing UI.
+const TRACE_LINK_FIELD: &str = "trace_link";
+
+/// Span fields interpreted by the OpenTelemetry layer, like `otel.name` and `otel.kind`.
+const CONTEXT_FIELD_PREFIXES_TO_IGNORE: [&str; 1] = ["otel."];
 
 struct JsonFormatter {
     service_name: String,
     pretty: bool,
+    schema: JsonSchema,
+    gcp_project_id: Option<String>,
+    baggage_keys: Vec<String>,
+    trace_link: bool,
 }
 
 impl JsonFormatter {
@@ -270,9 +597,33 @@ impl JsonFormatter {
         Self {
             service_name,
             pretty,
+            schema: JsonSchema::Default,
+            gcp_project_id: None,
+            baggage_keys: Vec::new(),
+            trace_link: false,
         }
     }
 
+    fn with_schema(mut self, schema: JsonSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    fn with_gcp_project_id(mut self, gcp_project_id: Option<String>) -> Self {
+        self.gcp_project_id = gcp_project_id;
+        self
+    }
+
+    fn with_baggage_keys(mut self, baggage_keys: Vec<String>) -> Self {
+        self.baggage_keys = baggage_keys;
+        self
+    }
+
+    fn with_trace_link(mut self, trace_link: bool) -> Self {
+        self.trace_link = trace_link;
+        self
+    }
+
     fn parse_from_service(&self, target: &str) -> u8 {
         match target.to_string().starts_with(&self.service_name) {
             true => 1,
@@ -289,32 +640,15 @@ impl JsonFormatter {
 
     fn log_without_context(&self, writer: Writer<'_>, event: &Event) {
         // extract fields from event
-        let event_fields = event.field_map();
-        let mut event_fields_as_value = match serde_json::to_value(event_fields) {
-            Ok(v) => v,
-            Err(_) => return, // unlikely to happen, so just ignore it for now
-        };
+        let mut event_visitor = JsonVisitor::default();
+        event.record(&mut event_visitor);
 
         // parse fields from alternative sources
         let field_timestamp: DateTime<Utc> = SystemTime::now().into();
 
         let field_target = event.metadata().target().to_string();
 
-        let field_thread_name = thread::current()
-            .name()
-            .map(|it| it.to_string())
-            .unwrap_or_default();
-
-        let field_message = event_fields_as_value
-            .get("message")
-            .and_then(|it| it.as_str())
-            .map(|it| it.to_string())
-            .unwrap_or_default();
-
-        // remove message from event fields because it is special and will go to a separe attribute
-        if let Some(obj) = event_fields_as_value.as_object_mut() {
-            obj.remove("message");
-        }
+        let field_trace_ids = TraceIds::from_context(&Context::current());
 
         let log_message = LogMessage {
             from_service: self.parse_from_service(&field_target),
@@ -329,7 +663,7 @@ impl JsonFormatter {
             line: event.metadata().line(),
 
             thread_id: "".to_string(),
-            thread_name: field_thread_name,
+            thread_name: current_thread_name(),
 
             current_span_simple: "".to_string(),
             current_span: "".to_string(),
@@ -339,14 +673,15 @@ impl JsonFormatter {
             root_span: "".to_string(),
             root_span_id: 0,
 
-            message: field_message,
-            fields: Value::Object(
-                event_fields_as_value
-                    .as_object()
-                    .cloned()
-                    .unwrap_or_default(),
-            ),
+            trace_id: field_trace_ids.trace_id,
+            span_id: field_trace_ids.span_id,
+
+            request_id: None,
+
+            message: event_visitor.message.unwrap_or_default(),
+            fields: Value::Object(event_visitor.fields),
             context: Value::Object(serde_json::Map::default()),
+            error: event_visitor.error,
         };
 
         self.log(writer, log_message);
@@ -357,17 +692,16 @@ impl JsonFormatter {
         &self,
         writer: Writer<'_>,
         event: &Event,
-        ot_event: opentelemetry::trace::Event,
         field_context: serde_json::Map<String, Value>,
-        field_fields: serde_json::Map<String, Value>,
+        event_visitor: JsonVisitor,
         field_root_span_id: u64,
         field_root_span_name: String,
         field_current_span_id: u64,
         field_current_span_name: String,
-        field_thread_id: String,
-        field_thread_name: String,
+        field_request_id: Option<String>,
+        field_trace_ids: TraceIds,
     ) {
-        let field_timestamp: DateTime<Utc> = ot_event.timestamp.into();
+        let field_timestamp: DateTime<Utc> = SystemTime::now().into();
         let field_target = event.metadata().target().to_string();
         let message = LogMessage {
             from_service: self.parse_from_service(&field_target),
@@ -381,8 +715,8 @@ impl JsonFormatter {
             file: event.metadata().file().map(|it| it.to_string()),
             line: event.metadata().line(),
 
-            thread_id: field_thread_id,
-            thread_name: field_thread_name,
+            thread_id: current_thread_id(),
+            thread_name: current_thread_name(),
 
             root_span: field_root_span_name.clone(),
             root_span_simple: self.parse_simple_name(field_root_span_name),
@@ -392,15 +726,22 @@ impl JsonFormatter {
             current_span_simple: self.parse_simple_name(field_current_span_name),
             current_span_id: field_current_span_id,
 
-            message: ot_event.name.to_string(),
-            fields: Value::Object(field_fields),
+            trace_id: field_trace_ids.trace_id,
+            span_id: field_trace_ids.span_id,
+
+            request_id: field_request_id,
+
+            message: event_visitor.message.unwrap_or_default(),
+            fields: Value::Object(event_visitor.fields),
             context: Value::Object(field_context),
+            error: event_visitor.error,
         };
 
         self.log(writer, message);
     }
 
     fn log(&self, mut writer: Writer<'_>, message: LogMessage) {
+        let message = self.render(message);
         let message_as_json_result = if self.pretty {
             serde_json::to_string_pretty(&message)
         } else {
@@ -412,13 +753,14 @@ impl JsonFormatter {
     }
 }
 
-impl<S> FormatEvent<S, DefaultFields> for JsonFormatter
+impl<S, N> FormatEvent<S, N> for JsonFormatter
 where
     S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
 {
     fn format_event(
         &self,
-        ctx: &FmtContext<'_, S, DefaultFields>,
+        ctx: &FmtContext<'_, S, N>,
         writer: Writer<'_>,
         event: &Event<'_>,
     ) -> std::fmt::Result {
@@ -433,11 +775,11 @@ where
             }
         };
 
-        let mut ot_event: Option<opentelemetry::trace::Event> = None;
         let mut field_root_span_id = 0u64;
         let mut field_root_span_name = "".to_string();
-        let mut field_thread_id = "".to_string();
-        let mut field_thread_name = "".to_string();
+        let mut field_request_id: Option<String> = None;
+        let mut field_trace_ids = TraceIds::default();
+        let mut field_baggage: Option<Context> = None;
 
         // ---------------------------------------------------------------------
         // 1 - visit context attributes
@@ -448,21 +790,18 @@ where
         let spans: Vec<SpanRef<S>> = current_span.scope().from_root().collect();
         for (index, span) in spans.iter().enumerate() {
             let is_root_span = index == 0;
-            let is_current_span = index == spans.len() - 1;
 
             // 1.1 - retrieve span data
             let span_ext = span.extensions();
-            let span_data = match span_ext.get::<OtelData>() {
-                Some(data) => data,
-                None => continue,
-            };
 
-            // 1.2 - keep track of current OpenTracing event for use after iteration
-            if is_current_span {
-                ot_event = match &span_data.builder.events {
-                    Some(events) => events.last().cloned(),
-                    None => None,
-                };
+            // 1.2 - keep track of OpenTelemetry ids, if exporting to OpenTelemetry, from the
+            // closest span exported, because the OpenTelemetry layer may filter out lower level spans
+            if let Some(span_data) = span_ext.get::<OtelData>() {
+                field_trace_ids = TraceIds::from_span_data(span_data);
+                // lower level spans inherit the baggage of higher level ones
+                if !self.baggage_keys.is_empty() {
+                    field_baggage = Some(span_data.parent_cx.clone());
+                }
             }
 
             // 1.3 - keep track of root span name for use after iteration
@@ -471,90 +810,155 @@ where
                 field_root_span_name = span.name().to_string();
             }
 
-            // 1.4 - retrieve span attributes
-            let span_attrs = match &span_data.builder.attributes {
-                Some(attrs) => attrs,
+            // 1.4 - retrieve span attributes stored by JsonSpanFields
+            let span_attrs = match span_ext.get::<JsonSpanAttributes>() {
+                Some(JsonSpanAttributes(attrs)) => attrs,
                 None => continue,
             };
 
             // 1.5 - populate context data
             for (key, value) in span_attrs {
-                // parse key
-                let key = key.to_string();
-
-                // track thread
-                if is_current_span && key == "thread.id" {
-                    field_thread_id = value.to_string();
-                }
-                if is_current_span && key == "thread.name" {
-                    field_thread_name = value.to_string();
+                // track request id, giving precedence to lower level spans
+                if key == REQUEST_ID_FIELD {
+                    field_request_id = value.as_str().map(|it| it.to_string());
+                    continue;
                 }
 
                 // check ignored fields
-                if CONTEXT_FIELDS_TO_IGNORE.contains(&key.as_str()) {
+                if CONTEXT_FIELD_PREFIXES_TO_IGNORE
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix))
+                {
                     continue;
                 }
 
                 // add attr to context if not already present because lower level attrs
                 // have precedence over higher level attrs if they have the same name
-                let value_wrapper = OpenTelemetryValue(value.clone());
-                if !field_context.contains_key(&key) {
-                    field_context.insert(key, value_wrapper.into());
+                if !field_context.contains_key(key) {
+                    field_context.insert(key.clone(), value.clone());
                 }
             }
         }
 
-        // ---------------------------------------------------------------------
-        // 2 - ensure has en event
-        // ---------------------------------------------------------------------
-        let ot_event = match ot_event {
-            Some(event) => event,
-            None => {
-                // unlikely to happen because if it has a span, it will have an event because this
-                // function is called only when event exists
-                self.log_without_context(writer, event);
-                return Ok(());
+        // 1.6 - add the link to the trace, computed once for the root span
+        if self.trace_link && !field_context.contains_key(TRACE_LINK_FIELD) {
+            if let Some(link) = spans.first().and_then(root_trace_link) {
+                field_context.insert(TRACE_LINK_FIELD.to_string(), Value::String(link));
             }
-        };
-
-        // ---------------------------------------------------------------------
-        // 3 - visit event attributes
-        // ---------------------------------------------------------------------
-        let mut field_fields = serde_json::Map::default();
-        for event in &ot_event.attributes {
-            // parse key
-            let key = event.key.to_string();
+        }
 
-            // check ignored fields
-            if EVENT_FIELDS_TO_IGNORE.contains(&key.as_str()) {
-                continue;
+        // 1.7 - add selected baggage entries, giving precedence to span attributes
+        if let Some(context) = field_baggage {
+            let baggage = context.baggage();
+            for key in &self.baggage_keys {
+                if let Some(value) = baggage.get(opentelemetry::Key::new(key.clone())) {
+                    if !field_context.contains_key(key) {
+                        field_context.insert(key.clone(), Value::String(value.to_string()));
+                    }
+                }
             }
-
-            // add event attr to context
-            let value_wrapper = OpenTelemetryValue(event.value.clone());
-            field_fields.insert(key.to_string(), value_wrapper.into());
         }
 
         // ---------------------------------------------------------------------
-        // 4 - output log message
+        // 2 - visit event attributes
+        // ---------------------------------------------------------------------
+        let mut event_visitor = JsonVisitor::default();
+        event.record(&mut event_visitor);
+
+        // ---------------------------------------------------------------------
+        // 3 - output log message
         // ---------------------------------------------------------------------
         self.log_with_context(
             writer,
             event,
-            ot_event,
             field_context,
-            field_fields,
+            event_visitor,
             field_root_span_id,
             field_root_span_name,
             current_span.id().into_u64(),
             current_span.name().into(),
-            field_thread_id,
-            field_thread_name,
+            field_request_id,
+            field_trace_ids,
         );
         Ok(())
     }
 }
 
+/// Formats events as JSON with the [`JsonFormatter`], along with the fields of their spans.
+fn json_layer<S, W>(formatter: JsonFormatter, writer: W) -> impl TracingLayer<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    W: for<'writer> MakeWriter<'writer> + 'static,
+{
+    JsonSpanFields.and_then(Layer::default().event_format(formatter).with_writer(writer))
+}
+
+/// Fields of a span, stored in its extensions by [`JsonSpanFields`].
+struct JsonSpanAttributes(serde_json::Map<String, Value>);
+
+/// Stores span fields as a JSON object in the span extensions, so the [`JsonFormatter`] can read
+/// them regardless of the OpenTelemetry layer being enabled.
+///
+/// They are kept as JSON values, so events only serialize them.
+struct JsonSpanFields;
+
+impl JsonSpanFields {
+    fn record(fields: &mut serde_json::Map<String, Value>, values: impl RecordFields) {
+        let mut visitor = JsonVisitor {
+            fields: std::mem::take(fields),
+            ..JsonVisitor::default()
+        };
+        values.record(&mut visitor);
+        *fields = visitor.fields;
+        if let Some(message) = visitor.message {
+            fields.insert("message".to_string(), Value::String(message));
+        }
+    }
+}
+
+impl<S> TracingLayer<S> for JsonSpanFields
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: LayerContext<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        // another JSON layer may have stored them already
+        let mut extensions = span.extensions_mut();
+        if extensions.get_mut::<JsonSpanAttributes>().is_none() {
+            let mut fields = serde_json::Map::new();
+            Self::record(&mut fields, attrs);
+            extensions.insert(JsonSpanAttributes(fields));
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: LayerContext<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(JsonSpanAttributes(fields)) = extensions.get_mut::<JsonSpanAttributes>() {
+            Self::record(fields, values);
+        }
+    }
+}
+
+fn current_thread_id() -> String {
+    // ThreadId only exposes its numeric value through Debug, formatted as "ThreadId(N)"
+    format!("{:?}", thread::current().id())
+        .trim_start_matches("ThreadId(")
+        .trim_end_matches(')')
+        .to_string()
+}
+
+fn current_thread_name() -> String {
+    thread::current()
+        .name()
+        .map(|it| it.to_string())
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Serialize)]
 struct LogMessage {
     from_service: u8,
@@ -578,120 +982,895 @@ struct LogMessage {
     current_span: String,
     current_span_id: u64,
 
+    trace_id: Option<String>,
+    span_id: Option<String>,
+
+    request_id: Option<String>,
+
     message: String,
     fields: Value,
     context: Value,
-}
 
-// -----------------------------------------------------------------------------
-// Converters
-// -----------------------------------------------------------------------------
-struct OpenTelemetryValue(opentelemetry::Value);
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<LogError>,
+}
 
-impl From<OpenTelemetryValue> for serde_json::Value {
-    fn from(value: OpenTelemetryValue) -> Self {
-        match &value.0 {
-            opentelemetry::Value::Bool(v) => Value::Bool(*v),
-            opentelemetry::Value::I64(v) => Value::from(*v),
-            opentelemetry::Value::F64(v) => Value::from(*v),
-            opentelemetry::Value::String(v) => Value::String(v.to_string()),
-            opentelemetry::Value::Array(_) => Value::String("".to_string()),
-        }
-    }
+/// Error logged with [`report_error`](crate::report_error) or recorded as a `dyn Error` field.
+#[derive(Debug, Serialize)]
+struct LogError {
+    kind: String,
+    chain: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backtrace: Option<String>,
 }
 
-pub trait RequestTracerPropagation<T> {
-    fn trace_request(self) -> T
-    where
-        Self: Sized,
-    {
-        self.trace_request_with_context(Span::current().context())
+impl From<ErrorReport> for LogError {
+    fn from(report: ErrorReport) -> Self {
+        Self {
+            kind: report.kind,
+            chain: report.chain,
+            backtrace: report.backtrace,
+        }
     }
-
-    fn trace_request_with_context(self, context: Context) -> T;
 }
 
-impl RequestTracerPropagation<RequestBuilder> for RequestBuilder {
-    fn trace_request_with_context(mut self, context: Context) -> RequestBuilder {
-        let mut header_carrier = HeaderCarrier { headers: vec![] };
-        global::get_text_map_propagator(|injector| {
-            injector.inject_context(&context, &mut header_carrier);
-        });
+// -----------------------------------------------------------------------------
+// Json Schemas
+// -----------------------------------------------------------------------------
+const ECS_VERSION: &str = "8.6.0";
 
-        for header in header_carrier.headers {
-            self = self.header(header.0, header.1)
+impl JsonFormatter {
+    /// Converts the log message to the layout of the configured schema.
+    fn render(&self, message: LogMessage) -> Value {
+        match self.schema {
+            JsonSchema::Default => serde_json::to_value(message).unwrap_or_default(),
+            JsonSchema::Ecs => self.render_ecs(message),
+            JsonSchema::Gcp => self.render_gcp(message),
+            JsonSchema::Datadog => self.render_datadog(message),
         }
+    }
 
-        self
+    fn render_ecs(&self, message: LogMessage) -> Value {
+        let mut json = JsonObject::default();
+        json.insert("@timestamp", message.timestamp);
+        json.insert("log.level", message.level.to_lowercase());
+        json.insert("message", message.message);
+        json.insert("ecs.version", ECS_VERSION);
+        json.insert("service.name", self.service_name.as_str());
+        json.insert("log.logger", message.target);
+        json.insert_some("log.origin.file.name", message.file);
+        json.insert_some("log.origin.file.line", message.line);
+        json.insert_non_empty("process.thread.name", message.thread_name);
+        json.insert_some("trace.id", message.trace_id);
+        json.insert_some("span.id", message.span_id);
+        json.insert_some("http.request.id", message.request_id);
+        if let Some(error) = message.error {
+            json.insert("error.type", error.kind);
+            json.insert_some("error.message", error.chain.first().cloned());
+            json.insert_some("error.stack_trace", error.backtrace);
+        }
+        json.insert("fields", message.fields);
+        json.insert("context", message.context);
+        json.into()
     }
-}
 
-impl RequestTracerPropagation<reqwest_middleware::RequestBuilder>
-    for reqwest_middleware::RequestBuilder
-{
-    fn trace_request_with_context(
-        mut self,
-        context: Context,
-    ) -> reqwest_middleware::RequestBuilder {
-        let mut header_carrier = HeaderCarrier { headers: vec![] };
-        global::get_text_map_propagator(|injector| {
-            injector.inject_context(&context, &mut header_carrier);
+    fn render_gcp(&self, message: LogMessage) -> Value {
+        let severity = match message.level.as_str() {
+            "TRACE" => "DEBUG",
+            "WARN" => "WARNING",
+            level => level,
+        };
+        let trace = message.trace_id.map(|trace_id| match &self.gcp_project_id {
+            Some(project_id) => format!("projects/{}/traces/{}", project_id, trace_id),
+            None => trace_id,
         });
 
-        for header in header_carrier.headers {
-            self = self.header(header.0, header.1)
-        }
+        let mut source_location = JsonObject::default();
+        source_location.insert_some("file", message.file);
+        source_location.insert_some("line", message.line.map(|line| line.to_string()));
+        source_location.insert("function", message.target);
+
+        let mut service_context = JsonObject::default();
+        service_context.insert("service", self.service_name.as_str());
+
+        let mut json = JsonObject::default();
+        json.insert("time", message.timestamp);
+        json.insert("severity", severity);
+        json.insert("message", message.message);
+        json.insert_some("logging.googleapis.com/trace", trace);
+        json.insert_some("logging.googleapis.com/spanId", message.span_id);
+        json.insert("logging.googleapis.com/sourceLocation", source_location);
+        json.insert("serviceContext", service_context);
+        json.insert_non_empty("thread", message.thread_name);
+        json.insert_some("request_id", message.request_id);
+        json.insert_some(
+            "error",
+            message
+                .error
+                .and_then(|error| serde_json::to_value(error).ok()),
+        );
+        json.insert("fields", message.fields);
+        json.insert("context", message.context);
+        json.into()
+    }
 
-        self
+    fn render_datadog(&self, message: LogMessage) -> Value {
+        let mut json = JsonObject::default();
+        json.insert("timestamp", message.timestamp);
+        json.insert("status", message.level.to_lowercase());
+        json.insert("message", message.message);
+        json.insert("service", self.service_name.as_str());
+        json.insert("logger.name", message.target);
+        json.insert_non_empty("logger.thread_name", message.thread_name);
+        json.insert_some(
+            "dd.trace_id",
+            message.trace_id.as_deref().and_then(datadog_trace_id),
+        );
+        json.insert_some(
+            "dd.span_id",
+            message.span_id.as_deref().and_then(datadog_span_id),
+        );
+        json.insert_some("request_id", message.request_id);
+        if let Some(error) = message.error {
+            json.insert("error.kind", error.kind);
+            json.insert_some("error.message", error.chain.first().cloned());
+            json.insert_some("error.stack", error.backtrace);
+        }
+        json.insert("fields", message.fields);
+        json.insert("context", message.context);
+        json.into()
     }
 }
 
-struct HeaderCarrier {
-    pub headers: Vec<(HeaderName, HeaderValue)>,
+/// Datadog trace ids are the decimal representation of the lower 64 bits of the trace id.
+fn datadog_trace_id(trace_id: &str) -> Option<String> {
+    u128::from_str_radix(trace_id, 16)
+        .ok()
+        .map(|trace_id| (trace_id as u64).to_string())
 }
 
-impl Injector for HeaderCarrier {
-    fn set(&mut self, key: &str, value: String) {
-        let header_name = HeaderName::from_str(key).expect("Must be header name");
-        let header_value = HeaderValue::from_str(&value).expect("Must be a header value");
-        self.headers.push((header_name, header_value));
-    }
+/// Datadog span ids are the decimal representation of the span id.
+fn datadog_span_id(span_id: &str) -> Option<String> {
+    u64::from_str_radix(span_id, 16)
+        .ok()
+        .map(|span_id| span_id.to_string())
 }
 
-#[derive(Clone, Copy, Debug)]
+/// JSON object that skips absent values.
+#[derive(Default)]
+struct JsonObject(serde_json::Map<String, Value>);
+
+impl JsonObject {
+    fn insert(&mut self, key: &str, value: impl Into<Value>) {
+        self.0.insert(key.to_string(), value.into());
+    }
+
+    fn insert_some(&mut self, key: &str, value: Option<impl Into<Value>>) {
+        if let Some(value) = value {
+            self.insert(key, value);
+        }
+    }
+
+    fn insert_non_empty(&mut self, key: &str, value: String) {
+        if !value.is_empty() {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl From<JsonObject> for Value {
+    fn from(object: JsonObject) -> Self {
+        Value::Object(object.0)
+    }
+}
+
+/// OpenTelemetry trace and span ids as hex strings, when valid.
+#[derive(Debug, Default)]
+pub(crate) struct TraceIds {
+    pub(crate) trace_id: Option<String>,
+    span_id: Option<String>,
+}
+
+impl TraceIds {
+    fn from_context(context: &Context) -> Self {
+        let span = context.span();
+        let span_context = span.span_context();
+        Self::new(span_context.trace_id(), span_context.span_id())
+    }
+
+    pub(crate) fn from_span_data(data: &OtelData) -> Self {
+        // the trace id is only assigned to the builder of spans without an active parent
+        let trace_id = if data.parent_cx.has_active_span() {
+            data.parent_cx.span().span_context().trace_id()
+        } else {
+            data.builder.trace_id.unwrap_or(TraceId::INVALID)
+        };
+        Self::new(trace_id, data.builder.span_id.unwrap_or(SpanId::INVALID))
+    }
+
+    fn new(trace_id: TraceId, span_id: SpanId) -> Self {
+        Self {
+            trace_id: (trace_id != TraceId::INVALID).then(|| trace_id.to_string()),
+            span_id: (span_id != SpanId::INVALID).then(|| span_id.to_string()),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Converters
+// -----------------------------------------------------------------------------
+struct OpenTelemetryValue(opentelemetry::Value);
+
+impl From<OpenTelemetryValue> for serde_json::Value {
+    fn from(value: OpenTelemetryValue) -> Self {
+        match &value.0 {
+            opentelemetry::Value::Bool(v) => Value::Bool(*v),
+            opentelemetry::Value::I64(v) => Value::from(*v),
+            opentelemetry::Value::F64(v) => Value::from(*v),
+            opentelemetry::Value::String(v) => Value::String(v.to_string()),
+            opentelemetry::Value::Array(opentelemetry::Array::Bool(v)) => Value::from(v.clone()),
+            opentelemetry::Value::Array(opentelemetry::Array::I64(v)) => Value::from(v.clone()),
+            opentelemetry::Value::Array(opentelemetry::Array::F64(v)) => Value::from(v.clone()),
+            opentelemetry::Value::Array(opentelemetry::Array::String(v)) => {
+                Value::Array(v.iter().map(|it| Value::String(it.to_string())).collect())
+            }
+        }
+    }
+}
+
+/// Collects the message and fields of an event as JSON values.
+///
+/// Values wrapped by [`as_json`] are kept as nested JSON, while other values recorded with `Debug`
+/// or `Display` are strings, whatever their content.
+#[derive(Default)]
+struct JsonVisitor {
+    message: Option<String>,
+    fields: serde_json::Map<String, Value>,
+    error: Option<LogError>,
+}
+
+impl JsonVisitor {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // fields added by tracing-log when converting log records
+        if field.name().starts_with("log.") {
+            return;
+        }
+        self.fields.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.insert(field, Value::String(value.to_string()));
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, Value::String(value.to_string()));
+        if self.error.is_none() {
+            self.error = Some(ErrorReport::from_error(value).into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else if field.name() == ERROR_REPORT_FIELD {
+            self.error = serde_json::from_str::<ErrorReport>(&format!("{:?}", value))
+                .ok()
+                .map(LogError::from);
+        } else {
+            self.insert(field, format_field(value));
+        }
+    }
+
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    fn record_value(&mut self, field: &Field, value: valuable::Value<'_>) {
+        self.insert(field, valuable_json::to_json(value));
+    }
+}
+
+/// Wraps a serializable value so it is recorded as JSON by tracing and rendered as a nested object
+/// by the JSON formatter.
+///
+/// ```
+/// # #[derive(balthazar::Serialize)]
+/// # struct Order { id: u64 }
+/// # let order = Order { id: 1 };
+/// balthazar::info!(order = %balthazar::as_json(&order), "order created");
+/// ```
+pub fn as_json<T: Serialize>(value: &T) -> AsJson<'_, T> {
+    AsJson(value)
+}
+
+pub struct AsJson<'a, T>(&'a T);
+
+thread_local! {
+    /// Length of the JSON last written by an [`AsJson`] on this thread.
+    static AS_JSON_WRITTEN: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+impl<T: Serialize> Display for AsJson<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_string(self.0) {
+            Ok(json) => {
+                AS_JSON_WRITTEN.with(|written| written.set(Some(json.len())));
+                f.write_str(&json)
+            }
+            Err(e) => write!(f, "<failed to serialize: {}>", e),
+        }
+    }
+}
+
+/// Formats a recorded value, parsing it as JSON when it is only an [`AsJson`].
+fn format_field(value: &dyn Debug) -> Value {
+    AS_JSON_WRITTEN.with(|written| written.set(None));
+    let formatted = format!("{:?}", value);
+    // a longer value wraps the JSON, like a vector of `AsJson`, so it is not JSON itself
+    if AS_JSON_WRITTEN.with(|written| written.take()) == Some(formatted.len()) {
+        if let Ok(structured) = serde_json::from_str(&formatted) {
+            return structured;
+        }
+    }
+    Value::String(formatted)
+}
+
+impl<T: Serialize> Debug for AsJson<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Converts `valuable` values recorded with `tracing::field::valuable` to JSON.
+#[cfg(all(tracing_unstable, feature = "valuable"))]
+mod valuable_json {
+    use serde_json::{Map, Value as Json};
+    use valuable::{NamedValues, Value, Visit};
+
+    pub(super) fn to_json(value: Value<'_>) -> Json {
+        match value {
+            Value::Bool(v) => Json::Bool(v),
+            Value::Char(v) => Json::String(v.to_string()),
+            Value::F32(v) => Json::from(v),
+            Value::F64(v) => Json::from(v),
+            Value::I8(v) => Json::from(v),
+            Value::I16(v) => Json::from(v),
+            Value::I32(v) => Json::from(v),
+            Value::I64(v) => Json::from(v),
+            Value::I128(v) => Json::String(v.to_string()),
+            Value::Isize(v) => Json::from(v),
+            Value::U8(v) => Json::from(v),
+            Value::U16(v) => Json::from(v),
+            Value::U32(v) => Json::from(v),
+            Value::U64(v) => Json::from(v),
+            Value::U128(v) => Json::String(v.to_string()),
+            Value::Usize(v) => Json::from(v),
+            Value::String(v) => Json::String(v.to_string()),
+            Value::Path(v) => Json::String(v.display().to_string()),
+            Value::Error(v) => Json::String(v.to_string()),
+            Value::Unit => Json::Null,
+            Value::Listable(v) => {
+                let mut visitor = ListVisitor::default();
+                v.visit(&mut visitor);
+                Json::Array(visitor.0)
+            }
+            Value::Tuplable(v) => {
+                let mut visitor = ListVisitor::default();
+                v.visit(&mut visitor);
+                Json::Array(visitor.0)
+            }
+            Value::Mappable(v) => {
+                let mut visitor = MapVisitor::default();
+                v.visit(&mut visitor);
+                Json::Object(visitor.0)
+            }
+            Value::Structable(v) => {
+                let mut visitor = FieldsVisitor::default();
+                v.visit(&mut visitor);
+                visitor.into_json()
+            }
+            Value::Enumerable(v) => {
+                let mut visitor = FieldsVisitor::default();
+                v.visit(&mut visitor);
+                let mut variant = Map::new();
+                variant.insert(v.variant().name().to_string(), visitor.into_json());
+                Json::Object(variant)
+            }
+            _ => Json::String(format!("{:?}", value)),
+        }
+    }
+
+    #[derive(Default)]
+    struct ListVisitor(Vec<Json>);
+
+    impl Visit for ListVisitor {
+        fn visit_value(&mut self, value: Value<'_>) {
+            self.0.push(to_json(value));
+        }
+    }
+
+    #[derive(Default)]
+    struct MapVisitor(Map<String, Json>);
+
+    impl Visit for MapVisitor {
+        fn visit_value(&mut self, _value: Value<'_>) {}
+
+        fn visit_entry(&mut self, key: Value<'_>, value: Value<'_>) {
+            let key = match to_json(key) {
+                Json::String(key) => key,
+                key => key.to_string(),
+            };
+            self.0.insert(key, to_json(value));
+        }
+    }
+
+    /// Named fields become an object and unnamed fields become an array.
+    #[derive(Default)]
+    struct FieldsVisitor {
+        named: Map<String, Json>,
+        unnamed: Vec<Json>,
+    }
+
+    impl FieldsVisitor {
+        fn into_json(self) -> Json {
+            if self.unnamed.is_empty() {
+                Json::Object(self.named)
+            } else {
+                Json::Array(self.unnamed)
+            }
+        }
+    }
+
+    impl Visit for FieldsVisitor {
+        fn visit_value(&mut self, value: Value<'_>) {
+            self.unnamed.push(to_json(value));
+        }
+
+        fn visit_named_fields(&mut self, named_values: &NamedValues<'_>) {
+            for (field, value) in named_values {
+                self.named.insert(field.name().to_string(), to_json(*value));
+            }
+        }
+
+        fn visit_unnamed_fields(&mut self, values: &[Value<'_>]) {
+            self.unnamed
+                .extend(values.iter().map(|value| to_json(*value)));
+        }
+    }
+}
+
+pub trait RequestTracerPropagation<T> {
+    fn trace_request(self) -> T
+    where
+        Self: Sized,
+    {
+        self.trace_request_with_context(Span::current().context())
+    }
+
+    fn trace_request_with_context(self, context: Context) -> T;
+}
+
+impl RequestTracerPropagation<RequestBuilder> for RequestBuilder {
+    fn trace_request_with_context(mut self, context: Context) -> RequestBuilder {
+        let mut header_carrier = HeaderCarrier { headers: vec![] };
+        global::get_text_map_propagator(|injector| {
+            injector.inject_context(&context, &mut header_carrier);
+        });
+        header_carrier.inject_request_id(&context);
+
+        for header in header_carrier.headers {
+            self = self.header(header.0, header.1)
+        }
+
+        self
+    }
+}
+
+impl RequestTracerPropagation<reqwest_middleware::RequestBuilder>
+    for reqwest_middleware::RequestBuilder
+{
+    fn trace_request_with_context(
+        mut self,
+        context: Context,
+    ) -> reqwest_middleware::RequestBuilder {
+        let mut header_carrier = HeaderCarrier { headers: vec![] };
+        global::get_text_map_propagator(|injector| {
+            injector.inject_context(&context, &mut header_carrier);
+        });
+        header_carrier.inject_request_id(&context);
+
+        for header in header_carrier.headers {
+            self = self.header(header.0, header.1)
+        }
+
+        self
+    }
+}
+
+struct HeaderCarrier {
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderCarrier {
+    fn inject_request_id(&mut self, context: &Context) {
+        if let Some(request_id) = context.get::<RequestIdValue>() {
+            self.headers
+                .push((request_id_header().clone(), request_id.0.clone()));
+        }
+    }
+}
+
+impl Injector for HeaderCarrier {
+    fn set(&mut self, key: &str, value: String) {
+        let header_name = HeaderName::from_str(key).expect("Must be header name");
+        let header_value = HeaderValue::from_str(&value).expect("Must be a header value");
+        self.headers.push((header_name, header_value));
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Baggage
+// -----------------------------------------------------------------------------
+/// Sets a baggage entry on the context of the current span.
+///
+/// The entry is inherited by spans created afterwards in the current span, propagated to
+/// outgoing HTTP requests and Kafka messages when the `baggage` propagator is enabled, and added
+/// to JSON formatted events when its key is listed in `TRACING_BAGGAGE_LOG_KEYS`.
+///
+/// Fails when there is no current span or when it is not exported to OpenTelemetry.
+pub fn set_baggage(
+    key: impl Into<opentelemetry::Key>,
+    value: impl Into<opentelemetry::Value>,
+) -> Result<()> {
+    let entry = opentelemetry::KeyValue::new(key, value);
+    Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let span = dispatch.downcast_ref::<Registry>()?.span(id)?;
+            let mut extensions = span.extensions_mut();
+            let span_data = extensions.get_mut::<OtelData>()?;
+            span_data.parent_cx = span_data.parent_cx.with_baggage([entry]);
+            Some(())
+        })
+        .flatten()
+        .ok_or_else(|| eyre::eyre!("No current span exported to OpenTelemetry to set baggage on"))
+}
+
+/// Returns the value of a baggage entry of the current span, set locally or propagated by the
+/// caller.
+pub fn baggage(key: &str) -> Option<String> {
+    Span::current()
+        .context()
+        .baggage()
+        .get(opentelemetry::Key::new(key.to_string()))
+        .map(|value| value.to_string())
+}
+
+// -----------------------------------------------------------------------------
+// HTTP Request Spans
+// -----------------------------------------------------------------------------
+/// Headers that carry credentials and are always redacted, unless explicitly allowed.
+const REDACTED_HEADERS: [&str; 8] = [
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+    "x-amz-security-token",
+    "x-api-key",
+    "x-auth-token",
+    "x-csrf-token",
+];
+
+/// Query string parameters that carry credentials and are always redacted.
+const REDACTED_QUERY_PARAMS: [&str; 7] = [
+    "access_token",
+    "api_key",
+    "apikey",
+    "password",
+    "secret",
+    "signature",
+    "token",
+];
+
+// -----------------------------------------------------------------------------
+// Request ID
+// -----------------------------------------------------------------------------
+const REQUEST_ID_FIELD: &str = "request_id";
+
+/// Request ID of the HTTP request being processed, stored in the OpenTelemetry context of its span
+/// so it is inherited by all child spans.
+#[derive(Clone, Debug)]
+struct RequestIdValue(HeaderValue);
+
+/// Returns the header carrying the request ID, configured by `TRACING_REQUEST_ID_HEADER`.
+pub fn request_id_header() -> &'static HeaderName {
+    REQUEST_ID_HEADER.get_or_init(|| HeaderName::from_static("x-request-id"))
+}
+
+/// Returns the request ID of the HTTP request being processed in the current span, if any.
+pub fn current_request_id() -> Option<String> {
+    Span::current()
+        .context()
+        .get::<RequestIdValue>()
+        .and_then(|request_id| request_id.0.to_str().ok())
+        .map(|request_id| request_id.to_string())
+}
+
+/// Creates HTTP request spans linked to the remote context propagated in the request headers.
+///
+/// All headers are recorded, and known credential headers (like `authorization` and `cookie`) and
+/// query string parameters (like `token` and `password`) are masked. Use
+/// [`MakeSpanWithRedaction`] to choose the recorded and masked ones.
+#[derive(Clone, Copy, Debug, Default)]
 pub struct MakeSpanWithContext;
 
 impl<B> MakeSpan<B> for MakeSpanWithContext {
     fn make_span(&mut self, req: &Request<B>) -> Span {
-        let remote_context = extract_remote_context(req.headers());
+        static DEFAULT_REDACTION: Lazy<Redaction> = Lazy::new(Redaction::default);
+        make_request_span(req, &DEFAULT_REDACTION)
+    }
+}
 
-        let http_route = if let Some(matched_path) = req.extensions().get::<MatchedPath>() {
-            matched_path.as_str().to_owned()
-        } else if let Some(uri) = req.extensions().get::<OriginalUri>() {
-            uri.0.path().to_owned()
-        } else {
-            req.uri().path().to_owned()
+/// Creates HTTP request spans like [`MakeSpanWithContext`], with the given headers and query
+/// string parameters recorded and masked.
+#[derive(Clone, Debug, Default)]
+pub struct MakeSpanWithRedaction {
+    redaction: Arc<Redaction>,
+}
+
+#[derive(Clone, Debug)]
+struct Redaction {
+    allowed_headers: Option<HashSet<String>>,
+    denied_headers: HashSet<String>,
+    redacted_headers: HashSet<String>,
+    redacted_query_params: HashSet<String>,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self {
+            allowed_headers: None,
+            denied_headers: HashSet::new(),
+            redacted_headers: REDACTED_HEADERS.iter().map(|it| it.to_string()).collect(),
+            redacted_query_params: REDACTED_QUERY_PARAMS
+                .iter()
+                .map(|it| it.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl MakeSpanWithRedaction {
+    /// Records only the given headers, omitting all others.
+    ///
+    /// Allowed headers that are also redacted are recorded with their values masked.
+    pub fn allow_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Arc::make_mut(&mut self.redaction).allowed_headers = Some(lowercase_set(headers));
+        self
+    }
+
+    /// Omits the given headers from the recorded headers.
+    pub fn deny_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Arc::make_mut(&mut self.redaction)
+            .denied_headers
+            .extend(lowercase_set(headers));
+        self
+    }
+
+    /// Masks the values of the given headers in addition to the default credential headers.
+    pub fn redact_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Arc::make_mut(&mut self.redaction)
+            .redacted_headers
+            .extend(lowercase_set(headers));
+        self
+    }
+
+    /// Masks the values of the given query string parameters in addition to the default
+    /// credential parameters.
+    pub fn redact_query_params<I, S>(mut self, params: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Arc::make_mut(&mut self.redaction)
+            .redacted_query_params
+            .extend(lowercase_set(params));
+        self
+    }
+}
+
+impl Redaction {
+    fn headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut recorded = HeaderMap::with_capacity(headers.len());
+        for (name, value) in headers {
+            let key = name.as_str();
+            if self.denied_headers.contains(key) {
+                continue;
+            }
+            if let Some(allowed) = &self.allowed_headers {
+                if !allowed.contains(key) {
+                    continue;
+                }
+            }
+
+            if self.redacted_headers.contains(key) {
+                recorded.append(name, HeaderValue::from_static(MASK));
+            } else {
+                recorded.append(name, value.clone());
+            }
+        }
+        recorded
+    }
+
+    fn uri(&self, uri: &Uri) -> String {
+        let query = match uri.query() {
+            Some(query) => query,
+            None => return uri.to_string(),
         };
 
-        let http_method_v = http_method(req.method());
-
-        let name = format!("{} {}", http_method_v, http_route);
-        let span = tracing::span!(
-            Level::INFO,
-            "HTTP request",
-            otel.name= %name,
-            method = %req.method(),
-            uri = %req.uri(),
-            version = ?req.version(),
-            headers = ?req.headers(),
-            status_code = tracing::field::Empty,
+        let redacted_query = query
+            .split('&')
+            .map(|param| match param.split_once('=') {
+                Some((name, _)) if self.redacted_query_params.contains(&name.to_lowercase()) => {
+                    format!("{}={}", name, MASK)
+                }
+                _ => param.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut recorded = uri.to_string();
+        recorded.truncate(recorded.len() - query.len());
+        recorded.push_str(&redacted_query);
+        recorded
+    }
+}
+
+fn lowercase_set<I, S>(values: I) -> HashSet<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    values
+        .into_iter()
+        .map(|it| it.as_ref().to_lowercase())
+        .collect()
+}
+
+impl<B> MakeSpan<B> for MakeSpanWithRedaction {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        make_request_span(req, &self.redaction)
+    }
+}
+
+fn make_request_span<B>(req: &Request<B>, redaction: &Redaction) -> Span {
+    let mut remote_context = extract_remote_context(req.headers());
+    let request_id = req.headers().get(request_id_header()).cloned();
+
+    let http_route = if let Some(matched_path) = req.extensions().get::<MatchedPath>() {
+        matched_path.as_str().to_owned()
+    } else if let Some(uri) = req.extensions().get::<OriginalUri>() {
+        uri.0.path().to_owned()
+    } else {
+        req.uri().path().to_owned()
+    };
+
+    let http_method_v = http_method(req.method());
+
+    let name = format!("{} {}", http_method_v, http_route);
+    let span = tracing::span!(
+        Level::INFO,
+        "HTTP request",
+        otel.name= %name,
+        otel.kind = "server",
+        otel.status_code = tracing::field::Empty,
+        otel.status_message = tracing::field::Empty,
+        method = %req.method(),
+        uri = %redaction.uri(req.uri()),
+        version = ?req.version(),
+        headers = ?redaction.headers(req.headers()),
+        request_id = tracing::field::Empty,
+        status_code = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+        response_size = tracing::field::Empty,
+    );
+    if let Some(request_id) = request_id {
+        if let Ok(value) = request_id.to_str() {
+            span.record(REQUEST_ID_FIELD, value);
+        }
+        remote_context = remote_context.with_value(RequestIdValue(request_id));
+    }
+    tracing_opentelemetry::OpenTelemetrySpanExt::set_parent(&span, remote_context);
+
+    span
+}
+
+/// Records the response status code, latency and size in the span created by
+/// [`MakeSpanWithContext`], marking the span as an error when the response is a server error.
+#[derive(Clone, Copy, Debug)]
+pub struct RecordResponse;
+
+impl<B: HttpBody> OnResponse<B> for RecordResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        let status = response.status();
+
+        span.record("status_code", status.as_u16());
+        span.record("latency_ms", latency.as_millis() as u64);
+        if let Some(size) = response_size(response) {
+            span.record("response_size", size);
+        }
+        if status.is_server_error() {
+            span.record("otel.status_code", "ERROR");
+        }
+
+        tracing::debug!(
+            status_code = status.as_u16(),
+            latency_ms = latency.as_millis() as u64,
+            "finished processing request"
         );
-        tracing_opentelemetry::OpenTelemetrySpanExt::set_parent(&span, remote_context);
+    }
+}
 
-        span
+/// Records the failure in the span created by [`MakeSpanWithContext`] and marks it as an error.
+///
+/// Failures are server error responses or errors returned by the inner service, the latter
+/// never reaching [`RecordResponse`].
+#[derive(Clone, Copy, Debug)]
+pub struct RecordFailure;
+
+impl<F: Display> OnFailure<F> for RecordFailure {
+    fn on_failure(&mut self, failure: F, latency: Duration, span: &Span) {
+        span.record("latency_ms", latency.as_millis() as u64);
+        span.record("otel.status_code", "ERROR");
+        span.record("otel.status_message", failure.to_string());
+
+        tracing::error!(
+            latency_ms = latency.as_millis() as u64,
+            "failed to process request: {}",
+            failure
+        );
     }
 }
 
+fn response_size<B: HttpBody>(response: &Response<B>) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .or_else(|| response.body().size_hint().exact())
+}
+
 fn extract_remote_context(headers: &HeaderMap) -> Context {
     struct HeaderExtractor<'a>(&'a HeaderMap);
 
@@ -731,7 +1910,7 @@ impl MakeRequestId for UuidMakeRequestId {
     fn make_request_id<B>(&mut self, request: &Request<B>) -> Option<RequestId> {
         let request_id: HeaderValue = request
             .headers()
-            .get("x-request-id")
+            .get(request_id_header())
             .cloned()
             .unwrap_or_else(|| Uuid::new_v4().to_string().parse().unwrap());
 
@@ -739,37 +1918,630 @@ impl MakeRequestId for UuidMakeRequestId {
     }
 }
 
-struct HoneycombTraceOnSentryScope {
-    team: String,
-    dataset: String,
-    env: String,
-}
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Body;
+    use tower::{service_fn, Layer as _, ServiceExt};
+    use tower_http::trace::TraceLayer;
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::layer::Context as LayerContext;
+
+    use super::*;
 
-impl HoneycombTraceOnSentryScope {
-    pub fn new(team: String, dataset: String, env: String) -> HoneycombTraceOnSentryScope {
-        HoneycombTraceOnSentryScope { team, dataset, env }
+    /// Collects every field recorded on spans after their creation.
+    #[derive(Clone, Default)]
+    struct RecordedFields(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for RecordedFields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
     }
-}
 
-impl<S: Subscriber> TracingLayer<S> for HoneycombTraceOnSentryScope {
-    fn on_enter(&self, _id: &Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
-        let context = Span::current().context();
-        let span = context.span();
-        let span_context = span.span_context();
+    impl<S: Subscriber> TracingLayer<S> for RecordedFields {
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: LayerContext<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
 
-        let trace_id = span_context.trace_id().to_string();
-        let supported_environments = vec!["staging", "production"];
+    impl RecordedFields {
+        fn get(&self, name: &str) -> Option<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .rev()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        }
+    }
 
-        if trace_id != NOOP_SPAN_ID && supported_environments.contains(&&*self.env) {
-            let trace_start = Utc::now().timestamp() - 600; // starts from 10 minutes before now
-            let trace_end = Utc::now().timestamp() + 600; // ends 10 minutes after now
+    /// Collects every line written by the formatter layer.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
 
-            sentry::configure_scope(|scope| {
-                let trace_url = format!("https://ui.honeycomb.io/{}/environments/{}/datasets/{}/trace?trace_id={}&trace_start_ts={}&trace_end_ts={}", self.team, self.env, self.dataset, trace_id, trace_start, trace_end);
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn json_lines(&self) -> Vec<Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    /// Subscriber formatting as JSON with an OpenTelemetry layer that generates valid ids.
+    ///
+    /// The returned provider must be kept alive while the subscriber is used.
+    fn json_subscriber(
+        logs: CapturedLogs,
+    ) -> (opentelemetry::sdk::trace::TracerProvider, impl Subscriber) {
+        json_subscriber_with_opentelemetry(logs, true)
+    }
+
+    fn json_subscriber_with_opentelemetry(
+        logs: CapturedLogs,
+        opentelemetry: bool,
+    ) -> (opentelemetry::sdk::trace::TracerProvider, impl Subscriber) {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let telemetry_layer = opentelemetry
+            .then(|| tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let subscriber = Registry::default().with(telemetry_layer).with(json_layer(
+            JsonFormatter::new("balthazar".to_string(), false),
+            move || logs.clone(),
+        ));
+        (provider, subscriber)
+    }
 
-                scope.set_extra("honeycomb_trace", Value::from(trace_url.clone()));
-                scope.set_tag("honeycomb_trace", trace_url);
+    async fn call_with_status(status: u16) -> RecordedFields {
+        let fields = RecordedFields::default();
+        let subscriber = Registry::default().with(fields.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service = TraceLayer::new_for_http()
+            .make_span_with(MakeSpanWithContext)
+            .on_response(RecordResponse)
+            .on_failure(RecordFailure)
+            .layer(service_fn(move |_: Request<Body>| async move {
+                Ok::<_, std::convert::Infallible>(
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from("body"))
+                        .unwrap(),
+                )
+            }));
+        service
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        fields
+    }
+
+    #[tokio::test]
+    async fn record_response_fields() {
+        let fields = call_with_status(200).await;
+
+        assert_eq!(fields.get("status_code"), Some("200".to_string()));
+        assert_eq!(fields.get("response_size"), Some("4".to_string()));
+        assert!(fields.get("latency_ms").is_some());
+        assert_eq!(fields.get("otel.status_code"), None);
+    }
+
+    #[tokio::test]
+    async fn record_server_error_as_span_error() {
+        let fields = call_with_status(503).await;
+
+        assert_eq!(fields.get("status_code"), Some("503".to_string()));
+        assert_eq!(
+            fields.get("otel.status_code"),
+            Some("\"ERROR\"".to_string())
+        );
+        assert!(fields.get("otel.status_message").is_some());
+    }
+
+    #[tokio::test]
+    async fn propagate_request_id() {
+        let subscriber = Registry::default().with(tracing_opentelemetry::layer());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service = TraceLayer::new_for_http()
+            .make_span_with(MakeSpanWithRedaction::default())
+            .layer(service_fn(|_: Request<Body>| async {
+                let outbound = reqwest::Client::new()
+                    .get("http://localhost")
+                    .trace_request()
+                    .build()
+                    .unwrap();
+                let response = Response::builder()
+                    .header("current", current_request_id().unwrap_or_default())
+                    .header("outbound", &outbound.headers()["x-request-id"])
+                    .body(Body::empty())
+                    .unwrap();
+                Ok::<_, std::convert::Infallible>(response)
+            }));
+        let response = service
+            .oneshot(
+                Request::get("/")
+                    .header("x-request-id", "abc")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["current"], "abc");
+        assert_eq!(response.headers()["outbound"], "abc");
+    }
+
+    #[test]
+    fn json_log_contains_trace_ids() {
+        let logs = CapturedLogs::default();
+        let (_provider, subscriber) = json_subscriber(logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("without span");
+            tracing::info_span!("root").in_scope(|| {
+                tracing::info!("in root span");
+                tracing::info_span!("child").in_scope(|| tracing::info!("in child span"));
+            });
+        });
+
+        let lines = logs.json_lines();
+        assert!(lines[0]["trace_id"].is_null());
+        assert!(lines[0]["span_id"].is_null());
+
+        let trace_id = lines[1]["trace_id"].as_str().unwrap();
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(lines[1]["span_id"].as_str().unwrap().len(), 16);
+        assert_eq!(lines[2]["trace_id"].as_str().unwrap(), trace_id);
+        assert_ne!(lines[2]["span_id"], lines[1]["span_id"]);
+    }
+
+    #[test]
+    fn json_log_contains_trace_ids_of_closest_exported_span() {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let logs = CapturedLogs::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let writer = logs.clone();
+        let subscriber = Registry::default()
+            .with(
+                tracing_opentelemetry::layer()
+                    .with_tracer(provider.tracer("test"))
+                    .with_filter(layer_filter("otel", "info").unwrap()),
+            )
+            .with(json_layer(
+                JsonFormatter::new("balthazar".to_string(), false),
+                move || writer.clone(),
+            ));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("root").in_scope(|| {
+                tracing::info!("in root span");
+                tracing::debug_span!("not exported").in_scope(|| {
+                    tracing::info!("in span not exported");
+                });
+            });
+        });
+
+        let lines = logs.json_lines();
+        assert!(lines[0]["trace_id"].is_string());
+        assert_eq!(lines[1]["trace_id"], lines[0]["trace_id"]);
+        assert_eq!(lines[1]["span_id"], lines[0]["span_id"]);
+        assert_eq!(lines[1]["current_span"], "not exported");
+    }
+
+    #[test]
+    fn json_log_context_does_not_depend_on_opentelemetry() {
+        let log = |opentelemetry: bool| {
+            let logs = CapturedLogs::default();
+            let (_provider, subscriber) =
+                json_subscriber_with_opentelemetry(logs.clone(), opentelemetry);
+            tracing::subscriber::with_default(subscriber, || {
+                let root = tracing::info_span!(
+                    "root",
+                    tenant = "t1",
+                    request_id = "abc",
+                    status = tracing::field::Empty,
+                    otel.kind = "server"
+                );
+                root.in_scope(|| {
+                    root.record("status", 200);
+                    tracing::info_span!("child", ids = ?vec![1, 2]).in_scope(|| {
+                        tracing::info!(count = 3, "in child span");
+                    });
+                });
             });
+            logs.json_lines().remove(0)
+        };
+
+        let with_opentelemetry = log(true);
+        let without_opentelemetry = log(false);
+        for key in [
+            "context",
+            "fields",
+            "message",
+            "request_id",
+            "root_span",
+            "current_span",
+        ] {
+            assert_eq!(
+                with_opentelemetry[key], without_opentelemetry[key],
+                "{}",
+                key
+            );
+        }
+        assert_eq!(
+            without_opentelemetry["context"],
+            serde_json::json!({ "tenant": "t1", "status": 200, "ids": "[1, 2]" })
+        );
+        assert_eq!(without_opentelemetry["request_id"], "abc");
+        assert!(without_opentelemetry["thread_id"]
+            .as_str()
+            .unwrap()
+            .parse::<u64>()
+            .is_ok());
+        assert!(without_opentelemetry["trace_id"].is_null());
+    }
+
+    #[test]
+    fn json_log_contains_selected_baggage() {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry::trace::TracerProvider as _;
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(json_layer(
+                JsonFormatter::new("balthazar".to_string(), false)
+                    .with_baggage_keys(vec!["tenant_id".to_string()]),
+                move || writer.clone(),
+            ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(set_baggage("tenant_id", "t1").is_err());
+
+            tracing::info_span!("root").in_scope(|| {
+                set_baggage("tenant_id", "t1").unwrap();
+                set_baggage("merchant_id", "m1").unwrap();
+
+                tracing::info_span!("child").in_scope(|| {
+                    assert_eq!(baggage("merchant_id").as_deref(), Some("m1"));
+
+                    let mut headers = std::collections::HashMap::new();
+                    opentelemetry::sdk::propagation::BaggagePropagator::new()
+                        .inject_context(&Span::current().context(), &mut headers);
+                    assert!(headers["baggage"].contains("tenant_id=t1"));
+
+                    tracing::info!("in child span");
+                });
+            });
+        });
+
+        let lines = logs.json_lines();
+        assert_eq!(lines[0]["context"]["tenant_id"], "t1");
+        assert!(lines[0]["context"].get("merchant_id").is_none());
+    }
+
+    #[test]
+    fn json_log_renders_structured_fields() {
+        #[derive(Serialize)]
+        struct Order {
+            id: u64,
+            tags: Vec<&'static str>,
+        }
+        let order = Order {
+            id: 1,
+            tags: vec!["a", "b"],
+        };
+
+        let logs = CapturedLogs::default();
+        let (_provider, subscriber) = json_subscriber(logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                order = %as_json(&order),
+                ids = ?vec![1, 2],
+                payload = %r#"{"id":1}"#,
+                orders = ?vec![as_json(&order)],
+                text = "[x]",
+                "without span"
+            );
+            tracing::info_span!("root").in_scope(|| {
+                tracing::info!(order = %as_json(&order), "in span");
+            });
+        });
+
+        let lines = logs.json_lines();
+        assert_eq!(lines[0]["message"], "without span");
+        assert_eq!(lines[0]["fields"]["order"]["id"], 1);
+        assert_eq!(
+            lines[0]["fields"]["order"]["tags"],
+            serde_json::json!(["a", "b"])
+        );
+        // only values wrapped by `as_json` are nested, whatever the content of the others
+        assert_eq!(lines[0]["fields"]["ids"], "[1, 2]");
+        assert_eq!(lines[0]["fields"]["payload"], r#"{"id":1}"#);
+        assert!(lines[0]["fields"]["orders"].is_string());
+        assert_eq!(lines[0]["fields"]["text"], "[x]");
+        assert_eq!(lines[1]["message"], "in span");
+        assert_eq!(lines[1]["fields"]["order"]["id"], 1);
+    }
+
+    #[cfg(all(tracing_unstable, feature = "valuable"))]
+    #[test]
+    fn json_log_renders_valuable_fields() {
+        let order = std::collections::HashMap::from([("id", 1u64)]);
+        let ids = vec![1u64, 2];
+
+        let logs = CapturedLogs::default();
+        let (_provider, subscriber) = json_subscriber(logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                order = tracing::field::valuable(&order),
+                ids = tracing::field::valuable(&ids),
+                "created"
+            );
+        });
+
+        let lines = logs.json_lines();
+        assert_eq!(lines[0]["fields"]["order"]["id"], 1);
+        // only values wrapped by `as_json` are nested, whatever the content of the others
+        assert_eq!(lines[0]["fields"]["ids"], "[1, 2]");
+        assert_eq!(lines[0]["fields"]["payload"], r#"{"id":1}"#);
+        assert!(lines[0]["fields"]["orders"].is_string());
+    }
+
+    #[test]
+    fn json_log_contains_error_object() {
+        use eyre::WrapErr;
+
+        let logs = CapturedLogs::default();
+        let (_provider, subscriber) = json_subscriber(logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let report = "x".parse::<u32>().wrap_err("Invalid quantity").unwrap_err();
+            crate::report_error(&report);
+
+            let error = std::io::Error::other("disk full");
+            tracing::error!(error = &error as &dyn std::error::Error, "write failed");
+        });
+
+        let lines = logs.json_lines();
+        assert_eq!(
+            lines[0]["message"],
+            "Invalid quantity: invalid digit found in string"
+        );
+        assert_eq!(lines[0]["error"]["kind"], "core::num::error::ParseIntError");
+        assert_eq!(
+            lines[0]["error"]["chain"],
+            serde_json::json!(["Invalid quantity", "invalid digit found in string"])
+        );
+        assert!(lines[0]["fields"].get("error.report").is_none());
+        assert_eq!(lines[1]["error"]["chain"], serde_json::json!(["disk full"]));
+        assert_eq!(lines[1]["fields"]["error"], "disk full");
+    }
+
+    #[test]
+    fn json_log_contains_panic() {
+        let logs = CapturedLogs::default();
+        let (_provider, subscriber) = json_subscriber(logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let payload: Box<dyn std::any::Any + Send> = Box::new("index out of bounds");
+            crate::error_reporting::report_panic(
+                payload.as_ref(),
+                Some(std::panic::Location::caller()),
+            );
+        });
+
+        let lines = logs.json_lines();
+        assert_eq!(lines[0]["level"], "ERROR");
+        assert!(lines[0]["message"]
+            .as_str()
+            .unwrap()
+            .ends_with("index out of bounds"));
+        assert!(lines[0]["fields"]["panic.location"]
+            .as_str()
+            .unwrap()
+            .starts_with("src/trace.rs"));
+        assert_eq!(lines[0]["error"]["kind"], "panic");
+        assert!(lines[0]["error"]["backtrace"].is_string());
+    }
+
+    fn golden_log_message() -> LogMessage {
+        LogMessage {
+            from_service: 1,
+            level: "WARN".to_string(),
+            timestamp: "2023-05-01T12:30:45.123Z".to_string(),
+            target_simple: "orders".to_string(),
+            target: "balthazar::orders".to_string(),
+            file: Some("src/orders.rs".to_string()),
+            line: Some(42),
+            thread_id: "7".to_string(),
+            thread_name: "tokio-runtime-worker".to_string(),
+            root_span_simple: "HTTP request".to_string(),
+            root_span: "HTTP request".to_string(),
+            root_span_id: 1,
+            current_span_simple: "create".to_string(),
+            current_span: "balthazar::orders::create".to_string(),
+            current_span_id: 2,
+            trace_id: Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string()),
+            span_id: Some("00f067aa0ba902b7".to_string()),
+            request_id: Some("9b2b6f1e-6f5e-4c1a-9d57-1f0c3b8a2e11".to_string()),
+            message: "order created".to_string(),
+            fields: serde_json::json!({ "order_id": 10, "tags": ["a", "b"] }),
+            context: serde_json::json!({ "method": "POST" }),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn json_schemas_match_golden_files() {
+        let schemas = [
+            (
+                JsonSchema::Default,
+                include_str!("../tests/golden/json_schema_default.json"),
+            ),
+            (
+                JsonSchema::Ecs,
+                include_str!("../tests/golden/json_schema_ecs.json"),
+            ),
+            (
+                JsonSchema::Gcp,
+                include_str!("../tests/golden/json_schema_gcp.json"),
+            ),
+            (
+                JsonSchema::Datadog,
+                include_str!("../tests/golden/json_schema_datadog.json"),
+            ),
+        ];
+        for (schema, golden) in schemas {
+            let formatter = JsonFormatter::new("balthazar".to_string(), false)
+                .with_schema(schema)
+                .with_gcp_project_id(Some("my-project".to_string()));
+            let rendered = formatter.render(golden_log_message());
+            let expected: Value = serde_json::from_str(golden).unwrap();
+            assert_eq!(rendered, expected, "schema {:?}", schema);
+        }
+    }
+
+    #[test]
+    fn convert_opentelemetry_arrays() {
+        let values = [
+            (
+                opentelemetry::Value::Array(opentelemetry::Array::Bool(vec![true, false])),
+                serde_json::json!([true, false]),
+            ),
+            (
+                opentelemetry::Value::Array(opentelemetry::Array::I64(vec![1, 2])),
+                serde_json::json!([1, 2]),
+            ),
+            (
+                opentelemetry::Value::Array(opentelemetry::Array::F64(vec![1.5])),
+                serde_json::json!([1.5]),
+            ),
+            (
+                opentelemetry::Value::Array(opentelemetry::Array::String(vec!["a".into()])),
+                serde_json::json!(["a"]),
+            ),
+        ];
+        for (value, expected) in values {
+            assert_eq!(Value::from(OpenTelemetryValue(value)), expected);
+        }
+    }
+
+    #[test]
+    fn redact_credential_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers.insert("x-custom-key", "secret".parse().unwrap());
+        headers.insert("host", "localhost".parse().unwrap());
+
+        let make_span = MakeSpanWithRedaction::default().redact_headers(["X-Custom-Key"]);
+        let recorded = make_span.redaction.headers(&headers);
+        assert_eq!(recorded["authorization"], MASK);
+        assert_eq!(recorded["x-custom-key"], MASK);
+        assert_eq!(recorded["host"], "localhost");
+
+        let make_span = MakeSpanWithRedaction::default()
+            .allow_headers(["authorization", "x-custom-key"])
+            .deny_headers(["x-custom-key"]);
+        let recorded = make_span.redaction.headers(&headers);
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded["authorization"], MASK);
+    }
+
+    #[test]
+    fn redact_credential_query_params() {
+        let make_span = MakeSpanWithRedaction::default().redact_query_params(["card"]);
+
+        let uri: Uri = "/path?Token=abc&page=2&card=1234&flag".parse().unwrap();
+        assert_eq!(
+            make_span.redaction.uri(&uri),
+            format!("/path?Token={}&page=2&card={}&flag", MASK, MASK)
+        );
+
+        let uri: Uri = "http://localhost/path".parse().unwrap();
+        assert_eq!(make_span.redaction.uri(&uri), "http://localhost/path");
+    }
+
+    #[test]
+    fn filter_layers_independently() {
+        let info_logs = CapturedLogs::default();
+        let debug_logs = CapturedLogs::default();
+        fn captured_json_layer<S>(logs: CapturedLogs) -> impl TracingLayer<S>
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            json_layer(
+                JsonFormatter::new("balthazar".to_string(), false),
+                move || logs.clone(),
+            )
         }
+
+        let subscriber = Registry::default()
+            .with(
+                captured_json_layer(info_logs.clone())
+                    .with_filter(layer_filter("info", "info").unwrap()),
+            )
+            .with(
+                captured_json_layer(debug_logs.clone())
+                    .with_filter(layer_filter("debug", "info,balthazar=debug").unwrap()),
+            );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("debug");
+            tracing::info!("info");
+        });
+
+        assert_eq!(info_logs.json_lines().len(), 1);
+        assert_eq!(debug_logs.json_lines().len(), 2);
+        assert!(layer_filter("invalid", "info,=[").is_err());
+    }
+
+    #[tokio::test]
+    async fn revert_log_filter_after_ttl() {
+        let (_layer, log_filter) = LogFilter::new("info").unwrap();
+
+        log_filter
+            .set("debug", Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(log_filter.directives().unwrap(), "debug");
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(log_filter.directives().unwrap(), "info");
+    }
+
+    #[tokio::test]
+    async fn keep_newer_log_filter_after_previous_ttl() {
+        let (_layer, log_filter) = LogFilter::new("info").unwrap();
+
+        log_filter
+            .set("debug", Some(Duration::from_millis(10)))
+            .unwrap();
+        log_filter.set("warn", None).unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(log_filter.directives().unwrap(), "warn");
     }
 }
@@ -0,0 +1,384 @@
+use chrono::{DateTime, Duration, Utc};
+use clap::ArgAction;
+use tracing::{span::Id, Span, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer, Registry};
+
+use crate::trace::TraceIds;
+use crate::Parser;
+
+// -----------------------------------------------------------------------------
+// Supported Providers
+// -----------------------------------------------------------------------------
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceLinkProvider {
+    /// Honeycomb trace view, requires `HONEYCOMB_TEAM` and `ENVIRONMENT`.
+    Honeycomb,
+
+    /// Jaeger UI trace view.
+    Jaeger,
+
+    /// Grafana Explore with the Tempo data source.
+    Tempo,
+}
+
+impl TraceLinkProvider {
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            TraceLinkProvider::Honeycomb => "https://ui.honeycomb.io",
+            TraceLinkProvider::Jaeger => "http://localhost:16686",
+            TraceLinkProvider::Tempo => "http://localhost:3000",
+        }
+    }
+
+    fn template(&self) -> &'static str {
+        match self {
+            TraceLinkProvider::Honeycomb => "{base_url}/{team}/environments/{environment}/datasets/{service}/trace?trace_id={trace_id}&trace_start_ts={start}&trace_end_ts={end}",
+            TraceLinkProvider::Jaeger => "{base_url}/trace/{trace_id}",
+            TraceLinkProvider::Tempo => "{base_url}/explore?left=%7B%22datasource%22:%22{datasource}%22,%22queries%22:%5B%7B%22queryType%22:%22traceql%22,%22query%22:%22{trace_id}%22%7D%5D,%22range%22:%7B%22from%22:%22{start_ms}%22,%22to%22:%22{end_ms}%22%7D%7D",
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Config
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Parser)]
+pub struct TraceLinkConfig {
+    /// Tracing UI linked from error reports and logs. No link is generated when not set.
+    #[clap(
+        value_enum,
+        long = "tracing-link-provider",
+        env = "TRACING_LINK_PROVIDER"
+    )]
+    pub trace_link_provider: Option<TraceLinkProvider>,
+
+    /// Address of the tracing UI. Defaults to the usual address of the provider.
+    #[clap(long = "tracing-link-base-url", env = "TRACING_LINK_BASE_URL")]
+    pub trace_link_base_url: Option<String>,
+
+    /// URL template replacing the one of the provider. Supports the `{base_url}`, `{trace_id}`,
+    /// `{service}`, `{environment}`, `{team}`, `{datasource}`, `{start}`, `{end}`, `{start_ms}`
+    /// and `{end_ms}` placeholders.
+    #[clap(long = "tracing-link-template", env = "TRACING_LINK_TEMPLATE")]
+    pub trace_link_template: Option<String>,
+
+    /// Honeycomb team, used by the `honeycomb` provider. Also selects the `honeycomb` provider when
+    /// `TRACING_LINK_PROVIDER` is not set, as before trace links were configurable, which is
+    /// deprecated and will be removed in the next release.
+    #[clap(long = "tracing-link-honeycomb-team", env = "HONEYCOMB_TEAM")]
+    pub honeycomb_team: Option<String>,
+
+    /// Grafana data source of Tempo, used by the `tempo` provider.
+    #[clap(
+        long = "tracing-link-tempo-datasource",
+        env = "TRACING_LINK_TEMPO_DATASOURCE",
+        default_value = "tempo"
+    )]
+    pub tempo_datasource: String,
+
+    /// Seconds before and after the start of the root span covered by the time range of the link.
+    #[clap(
+        long = "tracing-link-window-secs",
+        env = "TRACING_LINK_WINDOW_SECS",
+        default_value = "600"
+    )]
+    pub trace_link_window_secs: i64,
+
+    /// Whether the link is attached to errors reported to Sentry.
+    #[clap(
+        long = "tracing-link-error-reports",
+        env = "TRACING_LINK_ERROR_REPORTS",
+        default_value_t = true,
+        action = ArgAction::Set
+    )]
+    pub trace_link_error_reports: bool,
+
+    /// Whether the link is added to the context of JSON formatted events.
+    #[clap(
+        long = "tracing-link-logs",
+        env = "TRACING_LINK_LOGS",
+        default_value_t = false,
+        action = ArgAction::Set
+    )]
+    pub trace_link_logs: bool,
+}
+
+// -----------------------------------------------------------------------------
+// Links
+// -----------------------------------------------------------------------------
+
+/// Builds links to traces in the configured tracing UI.
+#[derive(Clone, Debug)]
+pub struct TraceLinks {
+    template: String,
+    values: Vec<(&'static str, String)>,
+    window: Duration,
+}
+
+impl TraceLinks {
+    /// Returns `None` when no provider nor template is configured.
+    pub fn new(
+        service_name: &str,
+        environment: Option<&str>,
+        config: &TraceLinkConfig,
+    ) -> Option<Self> {
+        // deprecated: `HONEYCOMB_TEAM` alone used to enable Honeycomb links
+        let provider = config.trace_link_provider.or_else(|| {
+            config
+                .honeycomb_team
+                .as_ref()
+                .map(|_| TraceLinkProvider::Honeycomb)
+        });
+        let template = config
+            .trace_link_template
+            .clone()
+            .or_else(|| provider.map(|provider| provider.template().to_string()))?;
+        let base_url = config
+            .trace_link_base_url
+            .as_deref()
+            .or_else(|| provider.map(|provider| provider.default_base_url()))
+            .map(|base_url| base_url.trim_end_matches('/').to_string());
+
+        // placeholders without a value are left out, so links needing them are not rendered
+        let values = [
+            ("service", Some(service_name.to_string())),
+            ("datasource", Some(config.tempo_datasource.clone())),
+            ("base_url", base_url),
+            ("environment", environment.map(str::to_string)),
+            ("team", config.honeycomb_team.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.filter(|value| !value.is_empty())?)))
+        .collect();
+
+        Some(Self {
+            template,
+            values,
+            window: Duration::seconds(config.trace_link_window_secs),
+        })
+    }
+
+    /// Renders the link to a trace started at the given time.
+    ///
+    /// Returns `None` when the template has a placeholder without a configured value.
+    pub fn link(&self, trace_id: &str, start: DateTime<Utc>) -> Option<String> {
+        let from = start - self.window;
+        let to = start + self.window;
+        let times = [
+            ("trace_id", trace_id.to_string()),
+            ("start", from.timestamp().to_string()),
+            ("end", to.timestamp().to_string()),
+            ("start_ms", from.timestamp_millis().to_string()),
+            ("end_ms", to.timestamp_millis().to_string()),
+        ];
+
+        let mut link = String::with_capacity(self.template.len() + trace_id.len());
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            let close = open + rest[open..].find('}')?;
+            let placeholder = &rest[open + 1..close];
+            let value = times
+                .iter()
+                .map(|(key, value)| (*key, value))
+                .chain(self.values.iter().map(|(key, value)| (*key, value)))
+                .find(|(key, _)| *key == placeholder)
+                .map(|(_, value)| value)?;
+            link.push_str(&rest[..open]);
+            link.push_str(value);
+            rest = &rest[close + 1..];
+        }
+        link.push_str(rest);
+        Some(link)
+    }
+}
+
+/// Link to the trace of a root span, stored in its extensions.
+struct TraceLink(Option<String>);
+
+/// Returns the link to the trace of the current span, when a trace link provider is configured.
+pub fn current_trace_link() -> Option<String> {
+    Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let span = dispatch.downcast_ref::<Registry>()?.span(id)?;
+            let root = span.scope().from_root().next()?;
+            let extensions = root.extensions();
+            extensions.get::<TraceLink>()?.0.clone()
+        })
+        .flatten()
+}
+
+/// Link to the trace of the root span of the given span, used by the JSON formatter.
+pub(crate) fn root_trace_link<S>(root: &tracing_subscriber::registry::SpanRef<S>) -> Option<String>
+where
+    S: for<'lookup> LookupSpan<'lookup>,
+{
+    root.extensions().get::<TraceLink>()?.0.clone()
+}
+
+// -----------------------------------------------------------------------------
+// Layer
+// -----------------------------------------------------------------------------
+
+/// Computes the trace link once per root span, when it is first entered, because the parent
+/// propagated by the caller is only set after the span is created.
+///
+/// Must be added after the OpenTelemetry layer, whose span data holds the trace id.
+pub(crate) struct TraceLinkLayer {
+    links: TraceLinks,
+}
+
+impl TraceLinkLayer {
+    pub(crate) fn new(links: TraceLinks) -> Self {
+        Self { links }
+    }
+}
+
+impl<S> Layer<S> for TraceLinkLayer
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        if span.parent().is_some() || span.extensions().get::<TraceLink>().is_some() {
+            return;
+        }
+
+        let trace_id = match span.extensions().get::<OtelData>() {
+            Some(data) => TraceIds::from_span_data(data).trace_id,
+            None => return,
+        };
+        let link = trace_id.and_then(|trace_id| self.links.link(&trace_id, Utc::now()));
+        span.extensions_mut().insert(TraceLink(link));
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Tests
+// -----------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use opentelemetry::trace::TracerProvider as _;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use crate::Parser;
+
+    use super::{current_trace_link, TraceLinkConfig, TraceLinkLayer, TraceLinks};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    fn links(args: &[&str], environment: Option<&str>) -> Option<TraceLinks> {
+        let config = TraceLinkConfig::parse_from([&["test"], args].concat());
+        TraceLinks::new("payments", environment, &config)
+    }
+
+    #[test]
+    fn render_provider_links() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+
+        let honeycomb = links(
+            &[
+                "--tracing-link-provider",
+                "honeycomb",
+                "--tracing-link-honeycomb-team",
+                "acme",
+            ],
+            Some("sandbox-eu"),
+        )
+        .unwrap();
+        assert_eq!(
+            honeycomb.link(TRACE_ID, start).unwrap(),
+            format!("https://ui.honeycomb.io/acme/environments/sandbox-eu/datasets/payments/trace?trace_id={}&trace_start_ts=1699999400&trace_end_ts=1700000600", TRACE_ID)
+        );
+
+        let jaeger = links(
+            &[
+                "--tracing-link-provider",
+                "jaeger",
+                "--tracing-link-base-url",
+                "https://jaeger.example.com/",
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            jaeger.link(TRACE_ID, start).unwrap(),
+            format!("https://jaeger.example.com/trace/{}", TRACE_ID)
+        );
+
+        let custom = links(
+            &[
+                "--tracing-link-template",
+                "https://traces/{service}/{trace_id}",
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            custom.link(TRACE_ID, start).unwrap(),
+            format!("https://traces/payments/{}", TRACE_ID)
+        );
+    }
+
+    #[test]
+    fn honeycomb_team_alone_selects_honeycomb() {
+        let start = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+
+        let honeycomb = links(
+            &["--tracing-link-honeycomb-team", "acme"],
+            Some("production"),
+        )
+        .unwrap();
+        assert!(honeycomb.link(TRACE_ID, start).unwrap().starts_with(
+            "https://ui.honeycomb.io/acme/environments/production/datasets/payments/"
+        ));
+
+        let jaeger = links(
+            &[
+                "--tracing-link-provider",
+                "jaeger",
+                "--tracing-link-honeycomb-team",
+                "acme",
+            ],
+            None,
+        )
+        .unwrap();
+        assert!(jaeger.link(TRACE_ID, start).unwrap().contains("/trace/"));
+    }
+
+    #[test]
+    fn no_link_without_required_values() {
+        assert!(links(&[], Some("production")).is_none());
+
+        let honeycomb = links(
+            &["--tracing-link-provider", "honeycomb"],
+            Some("production"),
+        );
+        assert!(honeycomb.unwrap().link(TRACE_ID, Utc::now()).is_none());
+    }
+
+    #[test]
+    fn link_root_span_trace() {
+        let links = links(&["--tracing-link-provider", "jaeger"], None).unwrap();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(TraceLinkLayer::new(links));
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(current_trace_link().is_none());
+
+            tracing::info_span!("root").in_scope(|| {
+                let link = current_trace_link().unwrap();
+                assert!(link.starts_with("http://localhost:16686/trace/"));
+
+                tracing::info_span!("child").in_scope(|| {
+                    assert_eq!(current_trace_link(), Some(link));
+                });
+            });
+        });
+    }
+}